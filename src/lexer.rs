@@ -1,4 +1,7 @@
+//! The lexer is still a work in progress and isn't wired up to anything yet.
+
 use std::collections::HashMap;
+use std::fmt;
 
 #[derive(Clone, Debug)]
 pub enum TokenType {
@@ -49,14 +52,14 @@ pub enum TokenType {
     Dot,        // .
 
     //End of File
-    EOF,
+    Eof,
 
     //tokens that throw an error
     Unknown,    // Exits with an error if encountered
 }
 
 impl From<TokenType> for String {
-    fn from(t: TokenType) -> String {
+    fn from(_from: TokenType) -> String {
         String::new() //TODO: Implement this
     }
 }
@@ -70,10 +73,10 @@ pub struct Token {
     column: usize,
 }
 
-impl Token {
-    pub fn to_string(&self) -> String {
-        String::from(self.tokentype.clone())
-    } 
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", String::from(self.tokentype.clone()))
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -107,7 +110,6 @@ impl Lexer {
         keywords.insert("self ", TokenType::This);
 
         let mut buffer = String::new();
-        let mut prevc: char;
         while let Some(c) = input.chars().next() {
             //TODO: Add peeking to next char
             self.current += 1;
@@ -119,9 +121,9 @@ impl Lexer {
             let (start, current, line) = (self.start, self.current, self.line);
             let token = |tokentype| {
                 Token {
-                    tokentype: tokentype,
+                    tokentype,
                     substring: String::from(&input[start..current]),
-                    line: line,
+                    line,
                     column: current,
                 }
             };
@@ -146,13 +148,12 @@ impl Lexer {
                 buffer.clear();
             }
 
-            prevc = c;
             //scan and accumulate tokens
         }
 
         //Adding EOF token to designate end of parsing
         self.tokens.push(Token {
-            tokentype: TokenType::EOF,
+            tokentype: TokenType::Eof,
             substring: String::from(&input[self.start..self.current]),
             line: self.line,
             column: self.current,
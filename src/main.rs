@@ -1,10 +1,149 @@
+// the lexer isn't wired up to anything yet
+#[allow(dead_code)]
 mod lexer;
 
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+
+use linefeed::Interface;
+
 use lexer::Lexer;
 use vdg_oxidizer::Repl;
+use vdg_oxidizer::VM;
+use vdg_oxidizer::assembler::Assembler;
+use vdg_oxidizer::debugger::Debugger;
+use vdg_oxidizer::object::{Object, MAGIC};
+use vdg_oxidizer::vm::trace::{TraceFormat, Tracer};
+
+const USAGE: &str = "Usage: verdigris [run [--trace|--trace-json <log>]|debug <file.vasm|file.vdgo>]";
+
+const EX_USAGE: i32 = 64;
+const EX_DATAERR: i32 = 65;
+const EX_NOINPUT: i32 = 66;
+const EX_SOFTWARE: i32 = 70;
+const EX_CANTCREAT: i32 = 73;
 
 fn main() {
-    let scanner = Lexer::new();
-    let mut repl = Repl::new();
-    repl.run();
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.iter().map(|s| s.as_str()).collect::<Vec<_>>().as_slice() {
+        [] | ["repl"] => {
+            let _scanner = Lexer::new();
+            let mut repl = Repl::new();
+            repl.run();
+        }
+        ["run", path] if !path.starts_with("--") => process::exit(run(path, None)),
+        ["run", "--trace", log, path] => process::exit(run(path, Some((log, TraceFormat::Text)))),
+        ["run", "--trace-json", log, path] => process::exit(run(path, Some((log, TraceFormat::Json)))),
+        ["debug", path] => process::exit(debug(path)),
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(EX_USAGE)
+        }
+    }
+}
+
+/// Runs an assembly source file or an object file, writing a trace of
+/// every instruction to `trace` if it is given.
+///
+/// Returns the exit status: the value of $0 when the program halts,
+/// or a sysexits code if it could not be loaded or faulted. The OS
+/// keeps only the low 8 bits of $0, so a program that halts with a
+/// value in 64..=73 looks the same as one of those failures; the
+/// message on stderr is what tells them apart.
+fn run(path: &str, trace: Option<(&str, TraceFormat)>) -> i32 {
+    let mut vm = match load(path) {
+        Ok((vm, _)) => vm,
+        Err(status) => return status,
+    };
+    if let Some((log, format)) = trace {
+        match Tracer::create(log, format) {
+            Ok(tracer) => vm.enable_trace(tracer),
+            Err(e) => {
+                eprintln!("verdigris: could not create {}: {}", log, e);
+                return EX_CANTCREAT
+            }
+        }
+    }
+
+    loop {
+        let pc = vm.pc();
+        match vm.run_once() {
+            Ok(true) => return vm.registers()[0],
+            Ok(false) => {}
+            Err(e) => {
+                eprintln!("{} (pc = {:#06x})", e, pc);
+                return EX_SOFTWARE
+            }
+        }
+    }
+}
+
+/// Loads an assembly source file or an object file into the debugger.
+fn debug(path: &str) -> i32 {
+    let (mut vm, labels) = match load(path) {
+        Ok(loaded) => loaded,
+        Err(status) => return status,
+    };
+
+    let interface = match Interface::new("verdigris") {
+        Ok(interface) => interface,
+        Err(e) => {
+            eprintln!("verdigris: could not open the terminal: {}", e);
+            return EX_SOFTWARE
+        }
+    };
+    let mut debugger = Debugger::new(&mut vm);
+    for (name, addr) in labels {
+        debugger.add_label(&name, addr);
+    }
+    match debugger.interact(&interface) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{}", e);
+            EX_SOFTWARE
+        }
+    }
+}
+
+/// Reads a program into a VM ready to run from its entry point, along
+/// with the address of each label it knows about.
+///
+/// Returns a sysexits code if the program could not be loaded.
+fn load(path: &str) -> Result<(VM, Vec<(String, usize)>), i32> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            eprintln!("verdigris: could not read {}: {}", path, e);
+            return Err(EX_NOINPUT)
+        }
+    };
+
+    let object = if bytes.starts_with(&MAGIC) {
+        match Object::from_bytes(&bytes) {
+            Ok(object) => object,
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                return Err(EX_DATAERR)
+            }
+        }
+    } else {
+        let source = String::from_utf8_lossy(&bytes);
+        let mut assembler = Assembler::new();
+        match assembler.assemble_file(Path::new(path), &source) {
+            Ok(_) => assembler.to_object(),
+            Err(e) => {
+                eprintln!("{}", e);
+                return Err(EX_DATAERR)
+            }
+        }
+    };
+    match object.to_vm() {
+        Ok(vm) => Ok((vm, object.labels())),
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            Err(EX_DATAERR)
+        }
+    }
 }
@@ -0,0 +1,244 @@
+//! Address expressions, the text between the brackets of a pointer operand.
+//!
+//! An address is a sum of terms, each of which is added or subtracted:
+//!
+//! ```text
+//! [main + 2]          label plus a constant offset
+//! [$4 - 8]            register base with a displacement
+//! [@table + $1 * 4]   label plus a scaled index register
+//! ```
+//!
+//! Labels may be written with or without the `@` sigil. An address can
+//! use at most two registers, neither of which may be subtracted, and
+//! only one of them may be scaled (by 1, 2, 4 or 8).
+
+use std::convert::TryFrom;
+use std::fmt;
+
+use crate::assembler::{AsmParseErr, Context};
+use crate::assembler::expr::Value;
+use crate::assembler::lexer::parse_number;
+
+/// A resolved address expression, computed by the VM as
+/// `base + index * scale + disp`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Address {
+    pub base: Option<u8>,
+    pub index: Option<u8>,
+    pub scale: u8,
+    pub disp: i32,
+}
+
+impl Address {
+    /// Parses an address expression, looking up labels with `label`.
+    ///
+    /// Also returns the value of the displacement,
+    /// which records the labels that it was made from.
+    pub fn parse<F>(text: &str, con: Context, label: F) -> Result<(Self, Value), AsmParseErr>
+    where
+        F: Fn(&str) -> Result<Value, AsmParseErr>,
+    {
+        let invalid = |reason: &str| {
+            AsmParseErr::InvalidAddress(format!("[{}]: {}", text.trim(), reason), con)
+        };
+
+        let mut addr = Address {
+            base: None,
+            index: None,
+            scale: 1,
+            disp: 0,
+        };
+        let mut disp = Value::absolute(0);
+        let add = |disp: Value, value: Value, negative: bool| {
+            let value = if negative { value.checked_mul(-1) } else { Some(value) };
+            value.and_then(|value| disp.checked_add(value)).ok_or_else(|| invalid("offset is too large"))
+        };
+        let terms = split_terms(text).ok_or_else(|| invalid("expected a term"))?;
+
+        for (negative, term) in terms {
+            let factors: Vec<&str> = term.split('*').map(|f| f.trim()).collect();
+            let (reg, scale) = match factors.as_slice() {
+                [atom] => match parse_atom(atom, con, &label)? {
+                    Atom::Register(reg) => (reg, None),
+                    Atom::Value(value) => {
+                        disp = add(disp, value, negative)?;
+                        continue
+                    }
+                },
+                [lhs, rhs] => match (parse_atom(lhs, con, &label)?, parse_atom(rhs, con, &label)?) {
+                    (Atom::Register(reg), Atom::Value(scale)) |
+                    (Atom::Value(scale), Atom::Register(reg)) => {
+                        if !scale.is_absolute() {
+                            return Err(invalid("scale must be 1, 2, 4 or 8"))
+                        }
+                        (reg, Some(scale.num))
+                    }
+                    (Atom::Value(lhs), Atom::Value(rhs)) => {
+                        let (value, factor) = match (lhs.is_absolute(), rhs.is_absolute()) {
+                            (_, true) => (lhs, rhs.num),
+                            (true, false) => (rhs, lhs.num),
+                            (false, false) => return Err(invalid("labels cannot be multiplied together")),
+                        };
+                        let value = value.checked_mul(factor).ok_or_else(|| invalid("offset is too large"))?;
+                        disp = add(disp, value, negative)?;
+                        continue
+                    }
+                    _ => return Err(invalid("registers cannot be multiplied together")),
+                },
+                _ => return Err(invalid("too many factors in a term")),
+            };
+
+            if negative {
+                return Err(invalid("registers cannot be subtracted"))
+            }
+            match scale {
+                Some(scale) => {
+                    if addr.index.is_some() && addr.scale == 1 {
+                        return Err(invalid("at most two registers can be used"))
+                    } else if addr.index.is_some() {
+                        return Err(invalid("only one register can be scaled"))
+                    }
+                    if ![1, 2, 4, 8].contains(&scale) {
+                        return Err(invalid("scale must be 1, 2, 4 or 8"))
+                    }
+                    addr.index = Some(reg);
+                    addr.scale = scale as u8;
+                }
+                None if addr.base.is_none() => addr.base = Some(reg),
+                None if addr.index.is_none() => addr.index = Some(reg),
+                None => return Err(invalid("at most two registers can be used")),
+            }
+        }
+
+        addr.disp = i32::try_from(disp.num).map_err(|_| invalid("offset is too large"))?;
+        Ok((addr, disp))
+    }
+
+    /// Returns true if the address does not depend on any registers.
+    pub fn is_absolute(&self) -> bool {
+        self.base.is_none() && self.index.is_none()
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut terms = Vec::new();
+        if let Some(base) = self.base {
+            terms.push(format!("${}", base));
+        }
+        if let Some(index) = self.index {
+            if self.scale == 1 {
+                terms.push(format!("${}", index));
+            } else {
+                terms.push(format!("${} * {}", index, self.scale));
+            }
+        }
+
+        write!(f, "[{}", terms.join(" + "))?;
+        if terms.is_empty() {
+            write!(f, "{}", self.disp)?;
+        } else if self.disp < 0 {
+            write!(f, " - {}", -(self.disp as i64))?;
+        } else if self.disp > 0 {
+            write!(f, " + {}", self.disp)?;
+        }
+        write!(f, "]")
+    }
+}
+
+enum Atom {
+    Register(u8),
+    Value(Value),
+}
+
+fn parse_atom<F>(atom: &str, con: Context, label: &F) -> Result<Atom, AsmParseErr>
+where
+    F: Fn(&str) -> Result<Value, AsmParseErr>,
+{
+    if let Some(reg) = atom.strip_prefix('$') {
+        return match reg.parse::<u8>() {
+            Ok(reg) if reg <= 31 => Ok(Atom::Register(reg)),
+            _ => Err(AsmParseErr::InvalidRegister(reg.parse().unwrap_or(u32::MAX), con)),
+        }
+    }
+    if let Some(num) = parse_number(atom) {
+        return Ok(Atom::Value(Value::absolute(num)))
+    }
+    let name = atom.strip_prefix('@').unwrap_or(atom);
+    Ok(Atom::Value(label(name)?))
+}
+
+/// Splits an expression on `+` and `-` into terms,
+/// each marked with whether it is subtracted.
+fn split_terms(text: &str) -> Option<Vec<(bool, &str)>> {
+    let mut terms = Vec::new();
+    let mut negative = false;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        if c == '+' || c == '-' {
+            let term = text[start..i].trim();
+            if term.is_empty() {
+                // a leading sign, as in `[-4]` or `[$1 + -4]`
+                if c == '-' {
+                    negative = !negative;
+                }
+            } else {
+                terms.push((negative, term));
+                negative = c == '-';
+            }
+            start = i + 1;
+        }
+    }
+    let term = text[start..].trim();
+    if term.is_empty() {
+        return None
+    }
+    terms.push((negative, term));
+    Some(terms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assembler::Label;
+    use crate::object::Target;
+
+    fn parse_value(text: &str) -> Result<(Address, Value), AsmParseErr> {
+        Address::parse(text, Context::from(1, 1), |name| match name {
+            "main" => Ok(Value::relative(16, Target::Section(Label::Code))),
+            name => Err(AsmParseErr::UndefinedLabel(name.to_string(), Context::from(1, 1))),
+        })
+    }
+
+    fn parse(text: &str) -> Result<Address, AsmParseErr> {
+        parse_value(text).map(|(addr, _)| addr)
+    }
+
+    #[test]
+    fn test_parse_address() {
+        assert_eq!(parse("main + 2").unwrap(), Address { base: None, index: None, scale: 1, disp: 18 });
+        assert_eq!(parse("-4").unwrap().disp, -4);
+        assert_eq!(parse("main + 0x10").unwrap().disp, 32);
+        assert_eq!(parse("$3 - 8").unwrap(), Address { base: Some(3), index: None, scale: 1, disp: -8 });
+        assert_eq!(
+            parse("@main + $1 * 4 + $2").unwrap(),
+            Address { base: Some(2), index: Some(1), scale: 4, disp: 16 }
+        );
+        assert_eq!(parse("4 * $1 + 2 * 3").unwrap().to_string(), "[$1 * 4 + 6]");
+
+        assert_eq!(parse_value("$1 + main").unwrap().1.targets(), Some(vec![Target::Section(Label::Code)]));
+        assert!(parse_value("main - main + 4").unwrap().1.is_absolute());
+    }
+
+    #[test]
+    fn test_invalid_address() {
+        assert!(matches!(parse("nowhere"), Err(AsmParseErr::UndefinedLabel(_, _))));
+        assert!(matches!(parse("main -"), Err(AsmParseErr::InvalidAddress(_, _))));
+        assert!(matches!(parse("8 - $1"), Err(AsmParseErr::InvalidAddress(_, _))));
+        assert!(matches!(parse("$1 * 3"), Err(AsmParseErr::InvalidAddress(_, _))));
+        assert!(matches!(parse("$1 * $2"), Err(AsmParseErr::InvalidAddress(_, _))));
+        assert!(matches!(parse("$1 + $2 + $3"), Err(AsmParseErr::InvalidAddress(_, _))));
+        assert!(matches!(parse("$40"), Err(AsmParseErr::InvalidRegister(40, _))));
+        assert!(matches!(parse("main * main"), Err(AsmParseErr::InvalidAddress(_, _))));
+    }
+}
@@ -1,14 +1,52 @@
+//! The Assembler takes the parsed instruction stream and lays it out
+//! into a bytecode image that can be loaded straight into the VM.
+//!
+//! Assembly happens in two phases. The first phase walks the program
+//! to work out the size of every instruction and the offset of every
+//! label, and the second phase encodes each instruction with its labels
+//! resolved to addresses.
+//!
+//! The resulting image is the code section followed by the data section.
+//! The sections can also be packaged into an `Object` to be saved to disk.
+//!
+//! Constants defined with `.equ` and `.set` are folded into the operands
+//! that use them. They are collected afresh in each phase, so a constant
+//! only has a value after the line that defines it.
+
+use std::cell::Cell;
 use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
 
+use crate::assembler::{expr, Address, AsmParseErr, Context, Directive, Operand, Parser, Preprocessor};
+use crate::assembler::expr::Value;
 use crate::assembler::parser::Parsed;
 
 use crate::instruction::Instruction;
+use crate::object::{Object, Relocation, Target};
 
 #[derive(Debug, Clone)]
 pub struct Assembler {
     phase: Phase,
     program: Vec<Parsed>,
     symbols: HashMap<String, Symbol>,
+    constants: HashMap<String, Constant>,
+    code: Vec<u8>,
+    data: Vec<u8>,
+    /// Where the data section starts in the image, once the code section
+    /// has been padded out to the data section's alignment.
+    data_base: usize,
+    /// The largest alignment asked for with `.align`.
+    data_align: usize,
+    globals: Vec<String>,
+    imports: Vec<String>,
+    relocations: Vec<Relocation>,
+    /// The offset and length of every string literal in the data section.
+    strings: Vec<(usize, usize)>,
+    allow_undefined: bool,
+    source_map: Vec<SourceLoc>,
+    include_paths: Vec<PathBuf>,
+    files: Vec<PathBuf>,
 }
 
 impl Assembler {
@@ -17,46 +55,765 @@ impl Assembler {
             phase: Phase::First,
             program: Vec::new(),
             symbols: HashMap::new(),
+            constants: HashMap::new(),
+            code: Vec::new(),
+            data: Vec::new(),
+            data_base: 0,
+            data_align: 1,
+            globals: Vec::new(),
+            imports: Vec::new(),
+            relocations: Vec::new(),
+            strings: Vec::new(),
+            allow_undefined: false,
+            source_map: Vec::new(),
+            include_paths: Vec::new(),
+            files: Vec::new(),
         }
     }
-    pub fn assemble(&mut self) -> Vec<u8> {
-        self.first_phase().unwrap();
+
+    /// Assembles a parsed program into a bytecode image.
+    pub fn assemble(&mut self, program: Vec<Parsed>) -> Result<Vec<u8>, AsmParseErr> {
+        self.program = program;
+        self.symbols.clear();
+        self.code.clear();
+        self.data.clear();
+        self.globals.clear();
+        self.imports.clear();
+        self.relocations.clear();
+        self.strings.clear();
+        self.source_map.clear();
+        self.data_align = 1;
+
+        self.phase = Phase::First;
+        self.constants.clear();
+        self.first_phase()?;
         self.phase = Phase::Second;
-        self.second_phase()
+        self.constants.clear();
+        self.second_phase()?;
+
+        let mut image = self.code.clone();
+        image.resize(self.data_base, 0);
+        image.extend_from_slice(&self.data);
+        Ok(image)
+    }
+
+    /// Tokenizes, preprocesses, parses and assembles a string of assembly source.
+    /// Includes are resolved from the working directory.
+    pub fn assemble_source(&mut self, source: &str) -> Result<Vec<u8>, AsmParseErr> {
+        self.assemble_file(Path::new(""), source)
+    }
+
+    /// Assembles the source of the file at `path`, resolving includes
+    /// relative to it. Errors are prefixed with the file they occurred in.
+    pub fn assemble_file(&mut self, path: &Path, source: &str) -> Result<Vec<u8>, AsmParseErr> {
+        let mut preprocessor = Preprocessor::new();
+        for dir in &self.include_paths {
+            preprocessor.add_include_path(dir.clone());
+        }
+
+        let result = preprocessor.process(source, path).and_then(|tokens| {
+            let mut parser = Parser::new();
+            let parsed = parser.parse(tokens)?;
+            self.assemble(parsed)
+        });
+        self.files = preprocessor.files().to_vec();
+
+        result.map_err(|err| match self.files.get(err.context().file) {
+            Some(file) if !file.as_os_str().is_empty() => {
+                AsmParseErr::InFile(file.display().to_string(), Box::new(err))
+            }
+            _ => err,
+        })
+    }
+
+    /// Lets `.global` name symbols that are not defined in this program,
+    /// so that they can be imported from another object by the linker.
+    /// Until then, they are assembled as if their address were 0.
+    pub fn allow_undefined(&mut self, allow: bool) {
+        self.allow_undefined = allow;
+    }
+
+    /// Adds a directory to search for files named by `.include`.
+    pub fn add_include_path<P: Into<PathBuf>>(&mut self, path: P) {
+        self.include_paths.push(path.into());
+    }
+
+    /// The files read during the last assembly, indexed by `Context::file`.
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// Maps every instruction and piece of data in the last assembly
+    /// back to where it came from in the source, in image order.
+    pub fn source_map(&self) -> &[SourceLoc] {
+        &self.source_map
+    }
+
+    /// Packages the last assembly into an object file, exporting every
+    /// symbol declared with `.global` and importing any left undefined.
+    /// The other labels are kept as local symbols, and execution starts at
+    /// `main` if it labels code.
+    pub fn to_object(&self) -> Object {
+        let symbols = self.globals.iter()
+            .map(|name| self.symbols[name].clone())
+            .collect();
+        let mut locals: Vec<Symbol> = self.symbols.values()
+            .filter(|sym| !self.globals.contains(&sym.name))
+            .cloned()
+            .collect();
+        locals.sort_by_key(|sym| (sym.kind as u8, sym.offset, sym.name.clone()));
+        let mut object = Object::new(
+            self.code.clone(),
+            self.data.clone(),
+            symbols,
+            self.imports.clone(),
+            self.relocations.clone(),
+        );
+        object.set_locals(locals);
+        object.set_strings(self.strings.clone());
+        object.set_data_align(self.data_align);
+        if let Some(main) = self.symbols.get("main").filter(|sym| sym.kind == Label::Code) {
+            object.set_entry(main.offset);
+        }
+        object
+    }
+
+    /// The symbols collected during the last assembly.
+    pub fn symbols(&self) -> &HashMap<String, Symbol> {
+        &self.symbols
+    }
+
+    /// Returns the address of a symbol in the assembled image.
+    pub fn address_of(&self, name: &str) -> Option<usize> {
+        self.symbols.get(name).map(|sym| match sym.kind {
+            Label::Code => sym.offset,
+            Label::Data => self.data_base + sym.offset,
+        })
+    }
+
+    /// Collects label offsets and works out the size of each section.
+    fn first_phase(&mut self) -> Result<(), AsmParseErr> {
+        let mut section = Label::Code;
+        let mut code_size = 0;
+        let mut data_size = 0;
+        let mut depth = 0;
+        let mut last_con = Context::from(1, 1);
+        let mut directive = None;
+        let mut exports = Vec::new();
+
+        for item in self.program.clone() {
+            last_con = item.context();
+            match item {
+                Parsed::Instruction(inst, con) => {
+                    if section != Label::Code {
+                        return Err(AsmParseErr::UnexpectedToken(
+                            format!("{:?}\nInstructions must be in the .code section.", inst.inst),
+                            con
+                        ))
+                    }
+                    code_size += self.encode(inst, code_size, con)?.0.len();
+                }
+                Parsed::LabelStart(name, con) => {
+                    if self.symbols.contains_key(&name) {
+                        return Err(AsmParseErr::DuplicateLabel(name, con))
+                    }
+                    let offset = match section {
+                        Label::Code => code_size,
+                        Label::Data => data_size,
+                    };
+                    self.symbols.insert(name.clone(), Symbol {
+                        name,
+                        kind: section,
+                        offset,
+                    });
+                    depth += 1;
+                }
+                Parsed::LabelEnd(con) => {
+                    if depth == 0 {
+                        return Err(AsmParseErr::UnexpectedToken(String::from("}"), con))
+                    }
+                    depth -= 1;
+                }
+                Parsed::Directive(dir, _) => {
+                    match dir {
+                        Directive::Code => section = Label::Code,
+                        Directive::Data => section = Label::Data,
+                        _ => {}
+                    }
+                    directive = Some(dir);
+                    continue
+                }
+                Parsed::Operand(op, con) => {
+                    match (directive, op) {
+                        (Some(Directive::Global), Operand::LabelUse(name)) => {
+                            exports.push((name, con));
+                        }
+                        (Some(dir), op) if dir.is_data() => {
+                            if section != Label::Data {
+                                return Err(AsmParseErr::UnexpectedToken(
+                                    format!("{}\nData must be in the .data section.", op),
+                                    con
+                                ))
+                            }
+                            data_size += self.data_bytes(dir, op, data_size, con)?.0.len();
+                        }
+                        (_, op) => {
+                            return Err(AsmParseErr::InvalidOperand(op, con))
+                        }
+                    }
+                    continue
+                }
+                Parsed::Constant(dir, name, value, con) => {
+                    self.define_constant(dir, name, value, con)?;
+                }
+                Parsed::StrLiteral(text, con) => {
+                    if section != Label::Data {
+                        return Err(AsmParseErr::UnexpectedToken(
+                            format!("\"{}\"\nStrings must be in the .data section.", text),
+                            con
+                        ))
+                    }
+                    data_size += text.len() + 1;
+                }
+            }
+            directive = None;
+        }
+
+        if depth != 0 {
+            return Err(AsmParseErr::UnexpectedEOF(last_con))
+        }
+        for (name, constant) in &self.constants {
+            if self.symbols.contains_key(name) {
+                return Err(AsmParseErr::DuplicateLabel(name.clone(), constant.con))
+            }
+        }
+        for (name, con) in exports {
+            if self.symbols.contains_key(&name) {
+                if !self.globals.contains(&name) {
+                    self.globals.push(name);
+                }
+            } else if self.allow_undefined {
+                if !self.imports.contains(&name) {
+                    self.imports.push(name);
+                }
+            } else {
+                return Err(AsmParseErr::UndefinedLabel(name, con))
+            }
+        }
+        self.data_base = code_size.next_multiple_of(self.data_align);
+        Ok(())
     }
 
-    fn first_phase(&mut self) -> Result<(), ()> {
-        // do stuff
-        unimplemented!()
+    /// Encodes every instruction and piece of data with all labels resolved.
+    fn second_phase(&mut self) -> Result<(), AsmParseErr> {
+        let mut directive = None;
+        for item in self.program.clone() {
+            match item {
+                Parsed::Directive(dir, _) => {
+                    directive = Some(dir);
+                    continue
+                }
+                Parsed::Operand(op, con) => {
+                    if let Some(dir) = directive.filter(|dir| dir.is_data()) {
+                        let (bytes, relocations) = self.data_bytes(dir, op, self.data.len(), con)?;
+                        self.relocations.extend(relocations);
+                        self.source_map.push(SourceLoc {
+                            address: self.data_base + self.data.len(),
+                            size: bytes.len(),
+                            context: con,
+                        });
+                        self.data.extend(bytes);
+                    }
+                    continue
+                }
+                Parsed::Instruction(inst, con) => {
+                    let (bytes, relocations) = self.encode(inst, self.code.len(), con)?;
+                    self.relocations.extend(relocations);
+                    self.source_map.push(SourceLoc {
+                        address: self.code.len(),
+                        size: bytes.len(),
+                        context: con,
+                    });
+                    self.code.extend(bytes);
+                }
+                Parsed::Constant(dir, name, value, con) => {
+                    self.define_constant(dir, name, value, con)?;
+                }
+                Parsed::StrLiteral(text, con) => {
+                    self.source_map.push(SourceLoc {
+                        address: self.data_base + self.data.len(),
+                        size: text.len() + 1,
+                        context: con,
+                    });
+                    self.strings.push((self.data.len(), text.len() + 1));
+                    self.data.extend_from_slice(text.as_bytes());
+                    self.data.push(0);
+                }
+                _ => {}
+            }
+            directive = None;
+        }
+        self.source_map.sort_by_key(|loc| loc.address);
+        Ok(())
+    }
+
+    /// Lays out one operand of a data directive, given the current size
+    /// of the data section, along with the relocations for any addresses.
+    ///
+    /// The data section is placed at a multiple of the largest alignment
+    /// in it, so aligning its offsets also aligns their addresses.
+    fn data_bytes(&mut self, dir: Directive, op: Operand, offset: usize, con: Context) -> Result<(Vec<u8>, Vec<Relocation>), AsmParseErr> {
+        let (value, uses_labels) = match &op {
+            Operand::NumLiteral(num) => (Value::absolute(*num as i64), false),
+            Operand::LabelUse(name) if dir == Directive::Word => (self.label_value(name, con)?, true),
+            Operand::Expr(text) => self.eval(text, con)?,
+            _ => return Err(AsmParseErr::InvalidOperand(op, con)),
+        };
+        if uses_labels && matches!(dir, Directive::Space | Directive::Align) {
+            return Err(AsmParseErr::InvalidExpression(
+                format!("{}: the size of data cannot depend on labels", op),
+                con
+            ))
+        }
+
+        let num = value.num;
+        match dir {
+            Directive::Word => {
+                let bytes = self.fold(num, &op, con)?.to_le_bytes().to_vec();
+                Ok((bytes, self.relocate(&value, Label::Data, offset, 4, &op, con)?))
+            }
+            Directive::Byte if value.is_absolute() && (-128..=255).contains(&num) => {
+                Ok((vec![num as u8], Vec::new()))
+            }
+            Directive::Space if (0..=i32::MAX as i64).contains(&num) => {
+                Ok((vec![0; num as usize], Vec::new()))
+            }
+            Directive::Align if num > 0 && num <= i32::MAX as i64 && (num as u32).is_power_of_two() => {
+                let align = num as usize;
+                self.data_align = self.data_align.max(align);
+                Ok((vec![0; offset.next_multiple_of(align) - offset], Vec::new()))
+            }
+            _ => Err(AsmParseErr::InvalidOperand(op, con)),
+        }
+    }
+
+    /// Resolves an instruction's operands and encodes it, given where
+    /// it starts in the code section, along with the relocations for
+    /// any addresses in it.
+    fn encode(&self, mut inst: Instruction, offset: usize, con: Context) -> Result<(Vec<u8>, Vec<Relocation>), AsmParseErr> {
+        let mut values = Vec::new();
+        for op in [&mut inst.op1, &mut inst.op2, &mut inst.op3].iter_mut() {
+            let original = op.clone();
+            let (resolved, value) = self.resolve(op.take(), con)?;
+            **op = resolved;
+            values.push((original, value));
+        }
+
+        let mut relocations = Vec::new();
+        for (field, (original, value)) in inst.value_fields().iter().zip(values) {
+            if let (Some((start, size)), Some(original), Some(value)) = (field, original, value) {
+                relocations.extend(self.relocate(&value, Label::Code, offset + start, *size, &original, con)?);
+            }
+        }
+
+        Ok((inst.to_bytes(), relocations))
+    }
+
+    /// Replaces labels with their addresses and evaluates pointer expressions,
+    /// also returning the value of the operand if it can hold an address.
+    ///
+    /// In the first phase, label addresses may not be known yet,
+    /// so they are replaced with a placeholder of the same size.
+    /// Pointers that use registers are encoded as indexed pointers,
+    /// which are the same size whatever the labels resolve to.
+    fn resolve(&self, op: Option<Operand>, con: Context) -> Result<(Option<Operand>, Option<Value>), AsmParseErr> {
+        match op {
+            Some(Operand::LabelUse(name)) => {
+                let value = self.label_value(&name, con)?;
+                Ok((Some(Operand::NumLiteral(value.num as i32)), Some(value)))
+            }
+            Some(Operand::Expr(text)) => {
+                let (value, _) = self.eval(&text, con)?;
+                let num = self.fold(value.num, &Operand::Expr(text), con)?;
+                Ok((Some(Operand::NumLiteral(num)), Some(value)))
+            }
+            Some(Operand::Pointer(ptr)) => {
+                let uses_labels = Cell::new(false);
+                let (addr, disp) = Address::parse(&ptr, con, |name| self.name_value(name, con, &uses_labels))?;
+                if addr.is_absolute() {
+                    Ok((Some(Operand::Pointer(addr.disp.to_string())), Some(disp)))
+                } else {
+                    Ok((Some(Operand::Address(addr)), Some(disp)))
+                }
+            }
+            op => Ok((op, None))
+        }
+    }
+
+    /// Works out the relocations for a field holding `value`,
+    /// which must add the address of each label it uses at most once.
+    fn relocate(&self, value: &Value, section: Label, offset: usize, size: usize, op: &Operand, con: Context) -> Result<Vec<Relocation>, AsmParseErr> {
+        let targets = value.targets()
+            .ok_or_else(|| AsmParseErr::NotRelocatable(op.to_string(), con))?;
+        Ok(targets.into_iter()
+            .map(|target| Relocation {
+                section,
+                offset,
+                size,
+                target,
+            })
+            .collect())
+    }
+
+    /// Defines a constant with `.equ`, which cannot be changed,
+    /// or `.set`, which can be set again further on.
+    fn define_constant(&mut self, dir: Directive, name: String, value: Operand, con: Context) -> Result<(), AsmParseErr> {
+        let (value, uses_labels) = match value {
+            Operand::NumLiteral(num) => (Value::absolute(num as i64), false),
+            Operand::LabelUse(label) => (self.label_value(&label, con)?, true),
+            Operand::Expr(text) => self.eval(&text, con)?,
+            op => return Err(AsmParseErr::InvalidOperand(op, con)),
+        };
+        if let Some(old) = self.constants.get(&name) {
+            if dir == Directive::Equ || old.fixed {
+                return Err(AsmParseErr::DuplicateLabel(name, con))
+            }
+        }
+        self.constants.insert(name, Constant {
+            value,
+            uses_labels,
+            fixed: dir == Directive::Equ,
+            con,
+        });
+        Ok(())
+    }
+
+    /// Evaluates a constant expression, also returning
+    /// whether its value depends on the address of a label.
+    fn eval(&self, text: &str, con: Context) -> Result<(Value, bool), AsmParseErr> {
+        let uses_labels = Cell::new(false);
+        let value = match expr::eval(text, con, |name| self.name_value(name, con, &uses_labels)) {
+            // labels are all placeholders in the first phase, so anything wrong
+            // with the value, like dividing by zero, is left for the second phase
+            Err(AsmParseErr::InvalidExpression(_, _)) if uses_labels.get() && matches!(self.phase, Phase::First) => {
+                Value::absolute(0)
+            }
+            value => value?,
+        };
+        Ok((value, uses_labels.get()))
+    }
+
+    /// Checks that a folded operand fits in 32 bits, allowing values
+    /// up to `u32::MAX` just as number literals do.
+    fn fold(&self, num: i64, op: &Operand, con: Context) -> Result<i32, AsmParseErr> {
+        if let Ok(num) = i32::try_from(num) {
+            return Ok(num)
+        }
+        u32::try_from(num)
+            .map(|num| num as i32)
+            .map_err(|_| AsmParseErr::InvalidExpression(format!("{}: value does not fit in 32 bits", op), con))
+    }
+
+    /// Looks up a name in an expression: a constant, or else a label.
+    /// Names written with `@` are always labels.
+    fn name_value(&self, name: &str, con: Context, uses_labels: &Cell<bool>) -> Result<Value, AsmParseErr> {
+        if let Some(constant) = self.constants.get(name) {
+            if constant.uses_labels {
+                uses_labels.set(true);
+            }
+            return Ok(constant.value.clone())
+        }
+        uses_labels.set(true);
+        self.label_value(name.strip_prefix('@').unwrap_or(name), con)
+    }
+
+    /// Looks up the address of a label, or a placeholder in the first phase.
+    /// Imported symbols are placeholders until the object is linked.
+    fn label_value(&self, name: &str, con: Context) -> Result<Value, AsmParseErr> {
+        if let Phase::First = self.phase {
+            return Ok(Value::absolute(0))
+        }
+        if let (Some(sym), Some(addr)) = (self.symbols.get(name), self.address_of(name)) {
+            return Ok(Value::relative(addr as i64, Target::Section(sym.kind)))
+        }
+        if self.imports.iter().any(|import| import == name) {
+            return Ok(Value::relative(0, Target::Symbol(name.to_string())))
+        }
+        Err(AsmParseErr::UndefinedLabel(name.to_string(), con))
     }
+}
 
-    fn second_phase(&mut self) -> Vec<u8> {
-        unimplemented!()
+impl Default for Assembler {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
+/// Where a span of the assembled image came from in the source.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SourceLoc {
+    pub address: usize,
+    pub size: usize,
+    pub context: Context,
+}
+
+/// A value named with `.equ` or `.set`.
+#[derive(Debug, Clone)]
+struct Constant {
+    value: Value,
+    uses_labels: bool,
+    fixed: bool,
+    con: Context,
+}
+
 #[derive(Debug, Clone, Copy)]
 enum Phase {
     First,
     Second,
 }
 
-#[derive(Debug, Clone)]
-pub enum AsmInst {
-    Instruction(Instruction),
-    Directive,
-    Label,
-}
-
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Label {
     Code,
     Data,
 }
 
+/// A label, along with the section it was declared in
+/// and its offset from the start of that section.
 #[derive(Debug, Clone, PartialEq)]
 pub struct Symbol {
     name: String,
     kind: Label,
-    data: Vec<Parsed>
-}
\ No newline at end of file
+    offset: usize,
+}
+
+impl Symbol {
+    pub fn new(name: String, kind: Label, offset: usize) -> Self {
+        Self {
+            name,
+            kind,
+            offset,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn kind(&self) -> Label {
+        self.kind
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::{Lexer, Parser};
+    use crate::vm::VM;
+    use crate::vm::vm::VMError;
+
+    fn assemble(code: &str) -> Result<Vec<u8>, AsmParseErr> {
+        let mut lexer = Lexer::new();
+        let tokens = lexer.tokenize(code)?;
+        let mut parser = Parser::new();
+        let parsed = parser.parse(tokens)?;
+        let mut assembler = Assembler::new();
+        assembler.assemble(parsed)
+    }
+
+    #[test]
+    fn test_assemble_and_run() {
+        let test_code = "mov $1 5\nadd $1 10 $2\nhlt";
+        let image = assemble(test_code).unwrap();
+
+        let mut test_vm = VM::new(image);
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.test_register(2).unwrap(), 15);
+    }
+
+    #[test]
+    fn test_forward_label_resolution() {
+        let test_code = "jmp @end\nmov $1 5\nend: { hlt }";
+        let image = assemble(test_code).unwrap();
+
+        // jmp 14
+        assert_eq!(&image[0..6], &[0x02, 0x00, 14, 0, 0, 0]);
+
+        let mut test_vm = VM::new(image);
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.test_register(1).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_label_operands() {
+        let test_code = "mov $1 0\nloop: { inc $1 $1\nlt $1 5\njeq @loop }\nmov $2 @msg\nhlt\n.data\nmsg: { .string \"hi\" }";
+        let mut assembler = Assembler::new();
+        let image = assembler.assemble_source(test_code).unwrap();
+        let msg = assembler.address_of("msg").unwrap();
+
+        let mut test_vm = VM::new(image);
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.test_register(1).unwrap(), 5);
+        assert_eq!(test_vm.test_register(2).unwrap(), msg as i32);
+    }
+
+    #[test]
+    fn test_pointer_expressions() {
+        let test_code = "mov $1 2\nmov $2 [@table + $1 * 4]\nmov [table + 4] 77\nmov $3 [table + 4]\nhlt\n.data\ntable: { .string \"aaaabbbbcccc\" }";
+        let image = assemble(test_code).unwrap();
+
+        let mut test_vm = VM::new(image);
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.test_register(2).unwrap(), i32::from_le_bytes(*b"cccc"));
+        assert_eq!(test_vm.test_register(3).unwrap(), 77);
+
+        let undefined = assemble("mov $1 [nowhere + 4]");
+        assert!(matches!(undefined, Err(AsmParseErr::UndefinedLabel(_, _))));
+
+        let image = assemble("mov $1 [5000]\nhlt").unwrap();
+        assert_eq!(VM::new(image).run(), Err(VMError::SegFault));
+    }
+
+    #[test]
+    fn test_data_section() {
+        let test_code = "hlt\n.data\nmsg: { .string \"hi\" }";
+
+        let mut lexer = Lexer::new();
+        let mut parser = Parser::new();
+        let parsed = parser.parse(lexer.tokenize(test_code).unwrap()).unwrap();
+        let mut assembler = Assembler::new();
+        let image = assembler.assemble(parsed).unwrap();
+
+        assert_eq!(image, vec![0x00, b'h', b'i', 0x00]);
+        assert_eq!(assembler.symbols()["msg"].kind(), Label::Data);
+        assert_eq!(assembler.symbols()["msg"].offset(), 0);
+        assert_eq!(assembler.address_of("msg"), Some(1));
+    }
+
+    #[test]
+    fn test_data_directives() {
+        let test_code = ".data\nbytes: { .byte 1 2 255 }\n.align 4\nwords: { .word 258 @end }\nbuf: { .space 3 }\n.code\nmov $1 [words]\nmov $2 [words + 4]\nend: { hlt }";
+        let mut assembler = Assembler::new();
+        let image = assembler.assemble_source(test_code).unwrap();
+        let end = assembler.address_of("end").unwrap() as u8;
+
+        assert_eq!(&image[assembler.address_of("bytes").unwrap()..], &[
+            1, 2, 255, 0,
+            2, 1, 0, 0,
+            end, 0, 0, 0,
+            0, 0, 0,
+        ]);
+        assert_eq!(assembler.symbols()["buf"].offset(), 12);
+        // the code is 25 bytes long, so the data section is moved along to 28
+        assert_eq!(assembler.address_of("bytes").unwrap(), 28);
+        assert_eq!(assembler.address_of("words").unwrap(), 32);
+
+        let mut test_vm = VM::new(image);
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.test_register(1).unwrap(), 258);
+        assert_eq!(test_vm.test_register(2).unwrap(), end as i32);
+    }
+
+    #[test]
+    fn test_invalid_data_directives() {
+        assert!(matches!(assemble(".data\n.byte 256"), Err(AsmParseErr::InvalidOperand(_, _))));
+        assert!(matches!(assemble(".data\n.align 3"), Err(AsmParseErr::InvalidOperand(_, _))));
+        assert!(matches!(assemble(".data\n.space -1"), Err(AsmParseErr::InvalidOperand(_, _))));
+        assert!(matches!(assemble(".data\n.word @nowhere"), Err(AsmParseErr::UndefinedLabel(_, _))));
+        assert!(matches!(assemble(".word 1"), Err(AsmParseErr::UnexpectedToken(_, _))));
+    }
+
+    #[test]
+    fn test_constants() {
+        let test_code = "\
+            .equ BUF_SIZE 16\n\
+            .equ FLAGS (1 << 4 | 0x3)\n\
+            .equ LEN (end - start)\n\
+            .set count 2\n\
+            mov $1 (BUF_SIZE * count + 1)\n\
+            .set count (count + 1)\n\
+            mov $2 (FLAGS & 0xf0 | count)\n\
+            mov $3 (LEN)\n\
+            mov $4 [buf + BUF_SIZE]\n\
+            hlt\n\
+            .data\n\
+            start: { .word BUF_SIZE (-BUF_SIZE / 4) }\n\
+            end: { .space (BUF_SIZE - 4) }\n\
+            buf: { .space BUF_SIZE .word 7 }";
+        let mut assembler = Assembler::new();
+        let image = assembler.assemble_source(test_code).unwrap();
+        let start = assembler.address_of("start").unwrap();
+        assert_eq!(&image[start..start + 8], &[16, 0, 0, 0, 0xfc, 0xff, 0xff, 0xff]);
+        assert_eq!(assembler.symbols()["buf"].offset(), 20);
+
+        let mut test_vm = VM::new(image);
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.test_register(1).unwrap(), 33);
+        assert_eq!(test_vm.test_register(2).unwrap(), 19);
+        assert_eq!(test_vm.test_register(3).unwrap(), 8);
+        assert_eq!(test_vm.test_register(4).unwrap(), 7);
+    }
+
+    #[test]
+    fn test_divide_by_label_difference() {
+        let test_code = "\
+            start: { mov $1 1 }\n\
+            end: { mov $0 (64 / (end - start)) }\n\
+            hlt";
+        let mut test_vm = VM::new(Assembler::new().assemble_source(test_code).unwrap());
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.test_register(0).unwrap(), 8);
+
+        let same = assemble("start: { end: { mov $0 (64 / (end - start)) } }");
+        assert!(matches!(same, Err(AsmParseErr::InvalidExpression(_, _))));
+    }
+
+    #[test]
+    fn test_invalid_constants() {
+        assert!(matches!(assemble(".equ A 1\n.equ A 2"), Err(AsmParseErr::DuplicateLabel(_, _))));
+        assert!(matches!(assemble(".equ A 1\n.set A 2"), Err(AsmParseErr::DuplicateLabel(_, _))));
+        assert!(matches!(assemble(".equ main 1\nmain: { hlt }"), Err(AsmParseErr::DuplicateLabel(_, _))));
+        assert!(matches!(assemble("mov $1 (A)\n.equ A 1"), Err(AsmParseErr::UndefinedLabel(_, _))));
+        assert!(matches!(assemble("mov $1 (1 / 0)"), Err(AsmParseErr::InvalidExpression(_, _))));
+        assert!(matches!(assemble("mov $1 (1 << 40)"), Err(AsmParseErr::InvalidExpression(_, _))));
+        assert!(matches!(assemble(".data\na: { .space (b - a) }\nb: { .byte 0 }"), Err(AsmParseErr::InvalidExpression(_, _))));
+        assert!(matches!(assemble(".equ 5 1"), Err(AsmParseErr::UnexpectedToken(_, _))));
+    }
+
+    #[test]
+    fn test_source_map() {
+        let test_code = "mov $1 5\n.data\nmsg: { .string \"hi\" }\n.code\nhlt";
+        let mut assembler = Assembler::new();
+        assembler.assemble_source(test_code).unwrap();
+
+        let map = assembler.source_map();
+        assert_eq!(map.len(), 3);
+        assert_eq!((map[0].address, map[0].size, map[0].context.line), (0, 8, 1));
+        assert_eq!((map[1].address, map[1].size, map[1].context.line), (8, 1, 5));
+        assert_eq!((map[2].address, map[2].size, map[2].context.line), (9, 3, 3));
+    }
+
+    #[test]
+    fn test_assembler_errors() {
+        let undefined = assemble("jmp @nowhere");
+        assert_eq!(undefined, Err(AsmParseErr::UndefinedLabel(
+            String::from("nowhere"), Context::from(1, 4)
+        )));
+
+        let duplicate = assemble("main: { hlt }\nmain: { hlt }");
+        assert!(matches!(duplicate, Err(AsmParseErr::DuplicateLabel(name, con)) if name == "main" && con.line == 2));
+
+        let unexported = assemble(".global @main\nhlt");
+        assert!(matches!(unexported, Err(AsmParseErr::UndefinedLabel(_, _))));
+
+        let unclosed = assemble("main: { hlt");
+        assert!(matches!(unclosed, Err(AsmParseErr::UnexpectedEOF(_))));
+
+        let misplaced = assemble(".data\nhlt");
+        assert!(matches!(misplaced, Err(AsmParseErr::UnexpectedToken(_, _))));
+    }
+}
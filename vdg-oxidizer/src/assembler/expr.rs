@@ -0,0 +1,334 @@
+//! Constant expressions, folded to a single value by the assembler.
+//!
+//! An expression is written in parentheses wherever a number can go,
+//! and may use named constants and labels:
+//!
+//! ```text
+//! .equ BUF_SIZE 256
+//! .equ LEN (end - start)
+//! mov $1 (BUF_SIZE * 2 + 1)
+//! ```
+//!
+//! The operators are, from loosest to tightest binding, `|`, `&`,
+//! `<< >>`, `+ -` and `* /`, along with a leading `-` for negation.
+//! Arithmetic is done on 64-bit integers, and division truncates.
+//!
+//! Since labels move when objects are linked together, a value keeps
+//! track of which labels it was made from. Labels can be added to and
+//! subtracted from each other, and multiplied by numbers, but the other
+//! operators only work on values that do not depend on labels.
+
+use crate::assembler::{AsmParseErr, Context};
+use crate::assembler::lexer::parse_number;
+use crate::object::Target;
+
+/// The value of an expression: a number, plus how many times over
+/// it depends on the address of each relocation target.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Value {
+    pub num: i64,
+    pub relocs: Vec<(Target, i64)>,
+}
+
+impl Value {
+    /// A value that does not depend on any labels.
+    pub fn absolute(num: i64) -> Self {
+        Self {
+            num,
+            relocs: Vec::new(),
+        }
+    }
+
+    /// The address of something in `target`.
+    pub fn relative(num: i64, target: Target) -> Self {
+        Self {
+            num,
+            relocs: vec![(target, 1)],
+        }
+    }
+
+    /// Returns true if the value does not move with any labels.
+    pub fn is_absolute(&self) -> bool {
+        self.relocs.is_empty()
+    }
+
+    /// Returns the targets the value moves with, if it can be relocated
+    /// by adding the address of each target once.
+    pub fn targets(&self) -> Option<Vec<Target>> {
+        self.relocs.iter()
+            .map(|(target, times)| if *times == 1 { Some(target.clone()) } else { None })
+            .collect()
+    }
+
+    pub fn checked_add(mut self, other: Value) -> Option<Self> {
+        self.num = self.num.checked_add(other.num)?;
+        for (target, times) in other.relocs {
+            match self.relocs.iter_mut().find(|(t, _)| *t == target) {
+                Some((_, n)) => *n = n.checked_add(times)?,
+                None => self.relocs.push((target, times)),
+            }
+        }
+        self.relocs.retain(|(_, times)| *times != 0);
+        Some(self)
+    }
+
+    pub fn checked_mul(mut self, factor: i64) -> Option<Self> {
+        self.num = self.num.checked_mul(factor)?;
+        for (_, times) in self.relocs.iter_mut() {
+            *times = times.checked_mul(factor)?;
+        }
+        self.relocs.retain(|(_, times)| *times != 0);
+        Some(self)
+    }
+}
+
+/// Evaluates an expression, looking up constants and labels with `name`.
+pub fn eval<F>(text: &str, con: Context, name: F) -> Result<Value, AsmParseErr>
+where
+    F: Fn(&str) -> Result<Value, AsmParseErr>,
+{
+    let invalid = |reason: &str| {
+        AsmParseErr::InvalidExpression(format!("({}): {}", text.trim(), reason), con)
+    };
+
+    let tokens = split_tokens(text).ok_or_else(|| invalid("unexpected character"))?;
+    let mut eval = Eval {
+        tokens,
+        pos: 0,
+        name: &name,
+        invalid: &invalid,
+    };
+    let value = eval.or()?;
+    if eval.pos < eval.tokens.len() {
+        return Err(invalid("expected an operator"))
+    }
+    Ok(value)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok<'a> {
+    Atom(&'a str),
+    Op(&'static str),
+}
+
+const OPERATORS: [&str; 10] = ["<<", ">>", "+", "-", "*", "/", "&", "|", "(", ")"];
+
+fn split_tokens(text: &str) -> Option<Vec<Tok<'_>>> {
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+            tokens.push(Tok::Op(op));
+            rest = &rest[op.len()..];
+        } else {
+            let len = rest
+                .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '.' || c == '@'))
+                .unwrap_or(rest.len());
+            if len == 0 {
+                return None
+            }
+            tokens.push(Tok::Atom(&rest[..len]));
+            rest = &rest[len..];
+        }
+        rest = rest.trim_start();
+    }
+    Some(tokens)
+}
+
+/// A recursive descent evaluator, with one method per precedence level.
+struct Eval<'a, F, E> {
+    tokens: Vec<Tok<'a>>,
+    pos: usize,
+    name: &'a F,
+    invalid: &'a E,
+}
+
+impl<'a, F, E> Eval<'a, F, E>
+where
+    F: Fn(&str) -> Result<Value, AsmParseErr>,
+    E: Fn(&str) -> AsmParseErr,
+{
+    fn or(&mut self) -> Result<Value, AsmParseErr> {
+        let mut value = self.and()?;
+        while self.eat("|") {
+            let rhs = self.and()?;
+            value = Value::absolute(self.num(value)? | self.num(rhs)?);
+        }
+        Ok(value)
+    }
+
+    fn and(&mut self) -> Result<Value, AsmParseErr> {
+        let mut value = self.shift()?;
+        while self.eat("&") {
+            let rhs = self.shift()?;
+            value = Value::absolute(self.num(value)? & self.num(rhs)?);
+        }
+        Ok(value)
+    }
+
+    fn shift(&mut self) -> Result<Value, AsmParseErr> {
+        let mut value = self.sum()?;
+        loop {
+            let left = if self.eat("<<") {
+                true
+            } else if self.eat(">>") {
+                false
+            } else {
+                return Ok(value)
+            };
+            let amount = self.sum()?;
+            let (num, amount) = (self.num(value)?, self.num(amount)?);
+            if !(0..64).contains(&amount) {
+                return Err((self.invalid)("shift amount must be from 0 to 63"))
+            }
+            value = Value::absolute(if left { num << amount } else { num >> amount });
+        }
+    }
+
+    fn sum(&mut self) -> Result<Value, AsmParseErr> {
+        let mut value = self.product()?;
+        loop {
+            value = if self.eat("+") {
+                value.checked_add(self.product()?)
+            } else if self.eat("-") {
+                let rhs = self.product()?;
+                value.checked_add(self.negate(rhs)?)
+            } else {
+                return Ok(value)
+            }.ok_or_else(|| (self.invalid)("value is too large"))?;
+        }
+    }
+
+    fn product(&mut self) -> Result<Value, AsmParseErr> {
+        let mut value = self.unary()?;
+        loop {
+            value = if self.eat("*") {
+                let rhs = self.unary()?;
+                // one side has to be a plain number for the result to be relocatable
+                let (value, factor) = if rhs.is_absolute() { (value, rhs.num) } else { (rhs, self.num(value)?) };
+                value.checked_mul(factor)
+                    .ok_or_else(|| (self.invalid)("value is too large"))?
+            } else if self.eat("/") {
+                let divisor = self.unary()?;
+                let (num, divisor) = (self.num(value)?, self.num(divisor)?);
+                if divisor == 0 {
+                    return Err((self.invalid)("division by zero"))
+                }
+                num.checked_div(divisor)
+                    .map(Value::absolute)
+                    .ok_or_else(|| (self.invalid)("value is too large"))?
+            } else {
+                return Ok(value)
+            };
+        }
+    }
+
+    fn unary(&mut self) -> Result<Value, AsmParseErr> {
+        if self.eat("-") {
+            let value = self.unary()?;
+            return self.negate(value)
+        }
+        self.atom()
+    }
+
+    fn negate(&self, value: Value) -> Result<Value, AsmParseErr> {
+        value.checked_mul(-1).ok_or_else(|| (self.invalid)("value is too large"))
+    }
+
+    /// Returns the number for an operator that only works on plain numbers.
+    fn num(&self, value: Value) -> Result<i64, AsmParseErr> {
+        if !value.is_absolute() {
+            return Err((self.invalid)("labels can only be added, subtracted or multiplied by a number"))
+        }
+        Ok(value.num)
+    }
+
+    fn atom(&mut self) -> Result<Value, AsmParseErr> {
+        match self.tokens.get(self.pos).cloned() {
+            Some(Tok::Op("(")) => {
+                self.pos += 1;
+                let value = self.or()?;
+                if !self.eat(")") {
+                    return Err((self.invalid)("unmatched parenthesis"))
+                }
+                Ok(value)
+            }
+            Some(Tok::Atom(atom)) => {
+                self.pos += 1;
+                if atom.starts_with(|c: char| c.is_ascii_digit()) {
+                    parse_number(atom)
+                        .map(Value::absolute)
+                        .ok_or_else(|| (self.invalid)("invalid number"))
+                } else {
+                    (self.name)(atom)
+                }
+            }
+            _ => Err((self.invalid)("expected a value")),
+        }
+    }
+
+    fn eat(&mut self, op: &'static str) -> bool {
+        if self.tokens.get(self.pos) == Some(&Tok::Op(op)) {
+            self.pos += 1;
+            return true
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assembler::Label;
+
+    fn eval_value(text: &str) -> Result<Value, AsmParseErr> {
+        eval(text, Context::from(1, 1), |name| match name {
+            "SIZE" => Ok(Value::absolute(256)),
+            "@start" => Ok(Value::relative(4, Target::Section(Label::Code))),
+            "end" => Ok(Value::relative(20, Target::Section(Label::Code))),
+            "ext" => Ok(Value::relative(0, Target::Symbol(String::from("ext")))),
+            name => Err(AsmParseErr::UndefinedLabel(name.to_string(), Context::from(1, 1))),
+        })
+    }
+
+    fn eval_text(text: &str) -> Result<i64, AsmParseErr> {
+        eval_value(text).map(|value| value.num)
+    }
+
+    #[test]
+    fn test_eval() {
+        assert_eq!(eval_text("1 + 2 * 3").unwrap(), 7);
+        assert_eq!(eval_text("(1 + 2) * 3").unwrap(), 9);
+        assert_eq!(eval_text("SIZE / 3 - -1").unwrap(), 86);
+        assert_eq!(eval_text("1 << 4 | 0x3 & 0b10").unwrap(), 18);
+        assert_eq!(eval_text("end - @start").unwrap(), 16);
+        assert_eq!(eval_text("-SIZE >> 4").unwrap(), -16);
+    }
+
+    #[test]
+    fn test_relocatable_values() {
+        assert!(eval_value("(end - @start) / 4").unwrap().is_absolute());
+        assert_eq!(
+            eval_value("end + 4").unwrap().targets(),
+            Some(vec![Target::Section(Label::Code)])
+        );
+        assert_eq!(
+            eval_value("ext + end - @start").unwrap().targets(),
+            Some(vec![Target::Symbol(String::from("ext"))])
+        );
+        assert_eq!(eval_value("end * 2").unwrap().targets(), None);
+        assert!(matches!(eval_value("end / 2"), Err(AsmParseErr::InvalidExpression(_, _))));
+        assert!(matches!(eval_value("end & 0xff"), Err(AsmParseErr::InvalidExpression(_, _))));
+    }
+
+    #[test]
+    fn test_invalid_eval() {
+        assert!(matches!(eval_text("1 +"), Err(AsmParseErr::InvalidExpression(_, _))));
+        assert!(matches!(eval_text("(1 + 2"), Err(AsmParseErr::InvalidExpression(_, _))));
+        assert!(matches!(eval_text("1 2"), Err(AsmParseErr::InvalidExpression(_, _))));
+        assert!(matches!(eval_text("SIZE / 0"), Err(AsmParseErr::InvalidExpression(_, _))));
+        assert!(matches!(eval_text("1 << 64"), Err(AsmParseErr::InvalidExpression(_, _))));
+        assert!(matches!(eval_text("$1 + 2"), Err(AsmParseErr::InvalidExpression(_, _))));
+        assert!(matches!(eval_text("missing"), Err(AsmParseErr::UndefinedLabel(_, _))));
+    }
+}
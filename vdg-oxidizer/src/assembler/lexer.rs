@@ -18,6 +18,7 @@ pub struct Lexer<'a> {
     code: Peekable<Chars<'a>>,
     state: Option<Token>,
     context: Context,
+    file: usize,
 }
 
 impl<'a> Lexer<'a> {
@@ -26,29 +27,27 @@ impl<'a> Lexer<'a> {
         Lexer {
             code: "".chars().peekable(),
             state: None,
-            context: Context::from(1, 1)
+            context: Context::from(1, 1),
+            file: 0,
         }
     }
 
-    //todo: add support for individual chars with single quotes
-    //todo: add support for escaped characters (\n, \t, etc)
+    /// Sets the file that the contexts of following tokens refer to.
+    pub fn set_file(&mut self, file: usize) {
+        self.file = file;
+    }
+
     /// Parses a single string into a token stream.
     pub fn tokenize(&mut self, code: &'a str) -> Result<Vec<Token>, AsmParseErr> {
         self.code = code.chars().peekable();
+        self.context = Context::from(1, 1);
+        self.context.file = self.file;
         let mut tokens = Vec::new();
         let mut buffer = String::new();
         while let Some(c) = self.code.next() {
             if c.is_whitespace() && c != ' ' {
                 if !buffer.is_empty() {
-                    if let Ok(num) = self.parse_as_number(&buffer) {
-                        tokens.push(Token::NumLiteral(num, self.context))
-                    } else {
-                        tokens.push(
-                            Token::Opcode(self.consume_opcode(buffer.clone()).ok_or(
-                                AsmParseErr::UnexpectedToken(buffer.clone(), self.context)
-                            )?, self.context)
-                        );
-                    }
+                    tokens.push(self.consume_word(&buffer)?);
                     buffer.clear();
                 }
                 if c == '\n' {
@@ -59,17 +58,26 @@ impl<'a> Lexer<'a> {
             }
             match c {
                 '$' => {
+                    self.context.column += 1;
                     let token = self.consume_register()?;
                     self.state = Some(token.clone());
                     tokens.push(token);
                     continue
                 }
                 '[' => {
+                    self.context.column += 1;
                     let token = self.consume_pointer()?;
                     self.state = Some(token.clone());
                     tokens.push(token);
                     continue
                 }
+                '(' => {
+                    self.context.column += 1;
+                    let token = self.consume_expr()?;
+                    self.state = Some(token.clone());
+                    tokens.push(token);
+                    continue
+                }
                 ':' => {
                     let token = Token::LabelDeclStart(buffer.clone(), self.context);
                     self.state = Some(token.clone());
@@ -89,26 +97,38 @@ impl<'a> Lexer<'a> {
                     continue
                 }
                 '@' => {
+                    self.context.column += 1;
                     tokens.push(self.consume_label_use()?);
                     continue
                 }
                 '"' => {
                     tokens.push(self.consume_str_lit()?);
                 }
+                '\'' => {
+                    tokens.push(self.consume_char_lit()?);
+                }
                 '.' => {
                     tokens.push(self.consume_directive()?);
                 }
+                ';' | '#' => {
+                    if !buffer.is_empty() {
+                        tokens.push(self.consume_word(&buffer)?);
+                        buffer.clear();
+                    }
+                    self.skip_line_comment();
+                    continue
+                }
+                '/' if self.code.peek() == Some(&'*') => {
+                    if !buffer.is_empty() {
+                        tokens.push(self.consume_word(&buffer)?);
+                        buffer.clear();
+                    }
+                    self.skip_block_comment()?;
+                    continue
+                }
                 ' ' => {
                     if !buffer.is_empty() {
-                        if let Ok(num) = self.parse_as_number(&buffer) {
-                            tokens.push(Token::NumLiteral(num, self.context))
-                        } else {
-                            tokens.push(
-                                Token::Opcode(self.consume_opcode(buffer.clone()).ok_or(
-                                    AsmParseErr::UnexpectedToken(buffer.clone(), self.context)
-                                )?, self.context)
-                            );
-                        }
+                        tokens.push(self.consume_word(&buffer)?);
                         buffer.clear();
                     }
                 }
@@ -174,14 +194,18 @@ impl<'a> Lexer<'a> {
     fn consume_register(&mut self) -> Result<Token, AsmParseErr> {
         let mut buf = String::new();
         loop {
+            if self.at_comment() {
+                return Ok(Token::Register(self.parse_as_register(&buf)?, self.context))
+            }
             let c = self.code.next();
             self.context.column += 1;
-            if c == None || c.unwrap().is_whitespace() {
+            if c.is_none() || c.unwrap().is_whitespace() {
+                let token = Token::Register(self.parse_as_register(&buf)?, self.context);
                 if let Some('\n') = c {
                     self.context.line += 1;
                     self.context.column = 1;
                 }
-                return Ok(Token::Register(self.parse_as_register(&buf)?, self.context))
+                return Ok(token)
             } else if let Some(c) = c {
                 buf.push(c);
             }
@@ -193,7 +217,7 @@ impl<'a> Lexer<'a> {
         loop {
             let c = self.code.next();
             self.context.column += 1;
-            if c == Some(']') || c == None {
+            if c == Some(']') || c.is_none() {
                 return Ok(Token::Pointer(buf, self.context))
             } else if let Some(c) = c {
                 if c == '\n' {
@@ -205,17 +229,48 @@ impl<'a> Lexer<'a> {
         }
     }
 
+    /// Reads a constant expression up to its matching `)`.
+    fn consume_expr(&mut self) -> Result<Token, AsmParseErr> {
+        let start = self.context;
+        let mut buf = String::new();
+        let mut depth = 0;
+        loop {
+            let c = self.code.next();
+            self.context.column += 1;
+            match c {
+                None => return Err(AsmParseErr::UnexpectedEOF(start)),
+                Some(')') if depth == 0 => return Ok(Token::Expr(buf, self.context)),
+                Some(c) => {
+                    match c {
+                        '(' => depth += 1,
+                        ')' => depth -= 1,
+                        '\n' => {
+                            self.context.line += 1;
+                            self.context.column = 1;
+                        }
+                        _ => {}
+                    }
+                    buf.push(c);
+                }
+            }
+        }
+    }
+
     fn consume_label_use(&mut self) -> Result<Token, AsmParseErr> {
         let mut buf = String::new();
         loop {
+            if self.at_comment() {
+                return Ok(Token::LabelUse(buf, self.context))
+            }
             let c = self.code.next();
             self.context.column += 1;
-            if c == None || c.unwrap().is_whitespace() {
+            if c.is_none() || c.unwrap().is_whitespace() {
+                let token = Token::LabelUse(buf, self.context);
                 if let Some('\n') = c {
                     self.context.line += 1;
                     self.context.column = 1;
                 }
-                return Ok(Token::LabelUse(buf, self.context))
+                return Ok(token)
             } else if let Some(c) = c {
                 buf.push(c);
             }
@@ -230,11 +285,7 @@ impl<'a> Lexer<'a> {
             if c == Some('"') {
                 return Ok(Token::StrLiteral(buf, self.context))
             } else if c == Some('\\') {
-                if let Some(c) = self.code.next() {
-                    buf.push(c);
-                } else {
-                    return Err(AsmParseErr::UnexpectedEOF(self.context))
-                }
+                buf.push(self.consume_escape()?);
                 continue
             } else if let Some(c) = c {
                 if c == '\n' {
@@ -242,23 +293,149 @@ impl<'a> Lexer<'a> {
                     self.context.column = 1;
                 }
                 buf.push(c);
-            } else if c == None {
+            } else if c.is_none() {
                 return Err(AsmParseErr::UnexpectedEOF(self.context))
             }
         }
     }
 
+    /// Returns true if a comment starts at the next character.
+    fn at_comment(&self) -> bool {
+        let mut ahead = self.code.clone();
+        match ahead.next() {
+            Some(';') | Some('#') => true,
+            Some('/') => ahead.next() == Some('*'),
+            _ => false,
+        }
+    }
+
+    /// Skips the rest of a `;` or `#` comment, up to the end of the line.
+    fn skip_line_comment(&mut self) {
+        self.context.column += 1;
+        while let Some(&c) = self.code.peek() {
+            if c == '\n' {
+                return
+            }
+            self.code.next();
+            self.context.column += 1;
+        }
+    }
+
+    /// Skips a `/* ... */` comment, which may span several lines.
+    fn skip_block_comment(&mut self) -> Result<(), AsmParseErr> {
+        let start = self.context;
+        self.code.next();
+        self.context.column += 2;
+        loop {
+            match self.code.next() {
+                Some('*') if self.code.peek() == Some(&'/') => {
+                    self.code.next();
+                    self.context.column += 2;
+                    return Ok(())
+                }
+                Some('\n') => {
+                    self.context.line += 1;
+                    self.context.column = 1;
+                }
+                Some(_) => self.context.column += 1,
+                None => return Err(AsmParseErr::UnexpectedEOF(start)),
+            }
+        }
+    }
+
+    /// Consumes a single-quoted character, producing its code point as a number.
+    fn consume_char_lit(&mut self) -> Result<Token, AsmParseErr> {
+        let start = self.context;
+        let mut chars = Vec::new();
+        loop {
+            let c = self.code.next();
+            self.context.column += 1;
+            match c {
+                Some('\'') => break,
+                Some('\\') => chars.push(self.consume_escape()?),
+                Some('\n') | None => return Err(AsmParseErr::UnexpectedEOF(self.context)),
+                Some(c) => chars.push(c),
+            }
+        }
+        if chars.len() != 1 {
+            let text: String = chars.into_iter().collect();
+            return Err(AsmParseErr::UnexpectedToken(
+                format!("'{}'\nCharacter literals must hold exactly one character.", text),
+                start
+            ))
+        }
+        Ok(Token::NumLiteral(chars[0] as i32, self.context))
+    }
+
+    /// Consumes an escape sequence after a backslash and returns the character it stands for.
+    fn consume_escape(&mut self) -> Result<char, AsmParseErr> {
+        let start = self.context;
+        let c = self.code.next().ok_or(AsmParseErr::UnexpectedEOF(self.context))?;
+        self.context.column += 1;
+        let escaped = match c {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            '0' => '\0',
+            '\\' => '\\',
+            '"' => '"',
+            '\'' => '\'',
+            'x' => {
+                let digits: String = (0..2).filter_map(|_| self.next_escape_char()).collect();
+                match u8::from_str_radix(&digits, 16) {
+                    Ok(byte) if digits.len() == 2 && is_hex(&digits) && byte <= 0x7f => byte as char,
+                    _ => return Err(AsmParseErr::InvalidEscape(format!("\\x{}", digits), start)),
+                }
+            }
+            'u' => {
+                let mut digits = String::new();
+                if self.next_escape_char() != Some('{') {
+                    return Err(AsmParseErr::InvalidEscape(String::from("\\u"), start))
+                }
+                loop {
+                    match self.next_escape_char() {
+                        Some('}') => break,
+                        Some(c) if digits.len() < 6 => digits.push(c),
+                        _ => return Err(AsmParseErr::InvalidEscape(format!("\\u{{{}", digits), start)),
+                    }
+                }
+                u32::from_str_radix(&digits, 16).ok()
+                    .filter(|_| is_hex(&digits))
+                    .and_then(std::char::from_u32)
+                    .ok_or_else(|| AsmParseErr::InvalidEscape(format!("\\u{{{}}}", digits), start))?
+            }
+            c => return Err(AsmParseErr::InvalidEscape(format!("\\{}", c), start)),
+        };
+        Ok(escaped)
+    }
+
+    /// Takes the next character of an escape sequence,
+    /// stopping at the end of the line or string.
+    fn next_escape_char(&mut self) -> Option<char> {
+        match self.code.peek() {
+            Some('\n') | Some('"') | Some('\'') | None => None,
+            Some(_) => {
+                self.context.column += 1;
+                self.code.next()
+            }
+        }
+    }
+
     fn consume_directive(&mut self) -> Result<Token, AsmParseErr> {
         let mut buf = String::new();
         loop {
+            if self.at_comment() {
+                return Ok(Token::Directive(Directive::try_from(buf, self.context)?, self.context))
+            }
             let c = self.code.next();
             self.context.column += 1;
-            if c == None || c.unwrap().is_whitespace() {
+            if c.is_none() || c.unwrap().is_whitespace() {
+                let token = Token::Directive(Directive::try_from(buf, self.context)?, self.context);
                 if let Some('\n') = c {
                     self.context.line += 1;
                     self.context.column = 1;
                 }
-                return Ok(Token::Directive(Directive::try_from(buf, self.context)?, self.context))
+                return Ok(token)
             } else if let Some(c) = c {
                 buf.push(c);
             }
@@ -266,37 +443,44 @@ impl<'a> Lexer<'a> {
     }
 
     fn consume_last_token(&mut self, mut last: String) -> Result<Token, AsmParseErr> {
-        if last.starts_with("$") {
-            return Ok(Token::Register(self.parse_as_register(&last[1..])?, self.context))
+        if let Some(reg) = last.strip_prefix('$') {
+            Ok(Token::Register(self.parse_as_register(reg)?, self.context))
         } else if last.starts_with("[") {
             if last.ends_with("]") {
                 last.pop();
-                return Ok(Token::Pointer(last[1..].to_string(), self.context))
+                Ok(Token::Pointer(last[1..].to_string(), self.context))
             } else {
-                return Err(AsmParseErr::UnexpectedEOF(self.context))
+                Err(AsmParseErr::UnexpectedEOF(self.context))
             }
         } else if last.ends_with(":") { // is label: if last, something is wrong
-            return Err(AsmParseErr::UnexpectedToken(last, self.context))
-        } else if last.starts_with("@") {
-            return Ok(Token::LabelUse(last[1..].to_string(), self.context))
+            Err(AsmParseErr::UnexpectedToken(last, self.context))
+        } else if let Some(name) = last.strip_prefix('@') {
+            Ok(Token::LabelUse(name.to_string(), self.context))
         } else {
-            if let Ok(num) = self.parse_as_number(&last) {
-                return Ok(Token::NumLiteral(num, self.context))
-            } else {
-                return Ok(
-                    Token::Opcode(self.consume_opcode(last.clone()).ok_or(
-                        AsmParseErr::UnexpectedToken(last, self.context)
-                    )?, self.context)
-                );
-            }
+            self.consume_word(&last)
         }
     }
 
+    /// Turns a bare word into a number, an opcode or an identifier.
+    ///
+    /// Anything starting with a digit is taken to be a number.
+    fn consume_word(&mut self, word: &str) -> Result<Token, AsmParseErr> {
+        let unsigned = word.strip_prefix('-').unwrap_or(word);
+        if unsigned.starts_with(|c: char| c.is_ascii_digit()) {
+            return Ok(Token::NumLiteral(self.parse_as_number(word)?, self.context))
+        }
+        match self.consume_opcode(word.to_string()) {
+            Some(op) => Ok(Token::Opcode(op, self.context)),
+            None => Ok(Token::Ident(word.to_string(), self.context)),
+        }
+    }
+
+    /// Parses a 32-bit number. Values up to `u32::MAX` are allowed
+    /// so that bit patterns like `0xffffffff` can be written directly.
     fn parse_as_number(&self, text: &str) -> Result<i32, AsmParseErr> {
-        if let Ok(num) = text.parse::<i32>() {
-            return Ok(num)
-        } else {
-            return Err(AsmParseErr::CouldNotParse(text.to_string(), self.context))
+        match parse_number(text) {
+            Some(num) if (i32::MIN as i64..=u32::MAX as i64).contains(&num) => Ok(num as i32),
+            _ => Err(AsmParseErr::CouldNotParse(text.to_string(), self.context)),
         }
     }
     
@@ -305,13 +489,53 @@ impl<'a> Lexer<'a> {
             if num > 31 {
                 return Err(AsmParseErr::InvalidRegister(num, self.context))
             }
-            return Ok(num as u8)
+            Ok(num as u8)
         } else {
-            return Err(AsmParseErr::CouldNotParse(text.to_string(), self.context))
+            Err(AsmParseErr::CouldNotParse(text.to_string(), self.context))
         }
     }
 }
 
+impl Default for Lexer<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Parses an integer literal: decimal (`42`), hexadecimal (`0x2a`),
+/// binary (`0b101010`) or octal (`0o52`), optionally negative and with
+/// `_` separators between digits.
+pub fn parse_number(text: &str) -> Option<i64> {
+    let (negative, text) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let prefix = text.get(..2).map(|p| p.to_ascii_lowercase());
+    let (radix, digits) = match prefix.as_deref() {
+        Some("0x") => (16, &text[2..]),
+        Some("0b") => (2, &text[2..]),
+        Some("0o") => (8, &text[2..]),
+        _ => (10, text),
+    };
+    if digits.starts_with('_') || digits.ends_with('_') {
+        return None
+    }
+    let digits: String = digits.chars().filter(|&c| c != '_').collect();
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return None
+    }
+    let num = i64::from_str_radix(&digits, radix).ok()?;
+    if negative {
+        Some(-num)
+    } else {
+        Some(num)
+    }
+}
+
+fn is_hex(digits: &str) -> bool {
+    digits.chars().all(|c| c.is_ascii_hexdigit())
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     Opcode(Opcode, Context),
@@ -323,34 +547,57 @@ pub enum Token {
     LabelDeclStart(String, Context),
     LabelDeclEnd(Context),
     Directive(Directive, Context),
+    Ident(String, Context),
+    Expr(String, Context),
 }
 
 impl Token {
     pub fn context(&self) -> Context {
         use Token::*;
         match self {
-            Opcode(_, con) => return *con,
-            Pointer(_, con) => return *con,
-            Register(_, con) => return *con,
-            LabelUse(_, con) => return *con,
-            NumLiteral(_, con) => return *con,
-            StrLiteral(_, con) => return *con,
-            LabelDeclStart(_, con) => return *con,
-            LabelDeclEnd(con) => return *con,
-            Directive(_, con) => return *con,
+            Opcode(_, con) => *con,
+            Pointer(_, con) => *con,
+            Register(_, con) => *con,
+            LabelUse(_, con) => *con,
+            NumLiteral(_, con) => *con,
+            StrLiteral(_, con) => *con,
+            LabelDeclStart(_, con) => *con,
+            LabelDeclEnd(con) => *con,
+            Directive(_, con) => *con,
+            Ident(_, con) => *con,
+            Expr(_, con) => *con,
+        }
+    }
+
+    /// Returns the same token found at a different place in the source.
+    pub fn with_context(mut self, new: Context) -> Self {
+        use Token::*;
+        match &mut self {
+            Opcode(_, con) |
+            Pointer(_, con) |
+            Register(_, con) |
+            LabelUse(_, con) |
+            NumLiteral(_, con) |
+            StrLiteral(_, con) |
+            LabelDeclStart(_, con) |
+            LabelDeclEnd(con) |
+            Directive(_, con) |
+            Ident(_, con) |
+            Expr(_, con) => *con = new,
         }
+        self
     }
 
     pub fn is_operand(&self) -> bool {
         use Token::*;
-        match self {
+        !matches!(self,
             Opcode(_,_) |
             StrLiteral(_,_) |
             LabelDeclStart(_,_) |
             LabelDeclEnd(_) |
-            Directive(_,_) => false,
-            _ => true
-        }
+            Directive(_,_) |
+            Ident(_,_)
+        )
     }
 }
 
@@ -385,6 +632,12 @@ impl fmt::Display for Token {
             Directive(dir, _) => {
                 write!(f, ".{:?}", dir)
             }
+            Ident(name, _) => {
+                write!(f, "{}", name)
+            }
+            Expr(expr, _) => {
+                write!(f, "({})", expr)
+            }
         }
     }
 }
@@ -393,13 +646,17 @@ impl fmt::Display for Token {
 pub struct Context {
     pub line: u32,
     pub column: u32,
+    /// Which source file the position is in, where 0 is the file
+    /// being assembled and included files are numbered after it.
+    pub file: usize,
 }
 
 impl Context {
     pub fn from(line: u32, col: u32) -> Self {
         Self {
-            line: line,
+            line,
             column: col,
+            file: 0,
         }
     }
 }
@@ -440,6 +697,67 @@ mod tests {
         ])
     }
 
+    #[test]
+    fn test_escapes_and_chars() {
+        let test_str = r#".string "a\tb\n\\ \"q\" \0\x41\u{e9}" mov $1 'a' mov $2 '\n'"#;
+        let mut lexer = Lexer::new();
+        let tokens = lexer.tokenize(test_str).unwrap();
+        assert_eq!(tokens[1], Token::StrLiteral(String::from("a\tb\n\\ \"q\" \0A\u{e9}"), tokens[1].context()));
+        assert_eq!(tokens[4], Token::NumLiteral(97, tokens[4].context()));
+        assert_eq!(tokens[7], Token::NumLiteral(10, tokens[7].context()));
+
+        for bad in &[r#""\q""#, r#""\x8""#, r#""\xff""#, r#""\u{110000}""#, r#""\u41""#, r#""\x+7""#] {
+            assert!(matches!(lexer.tokenize(bad), Err(AsmParseErr::InvalidEscape(_, _))), "{}", bad);
+        }
+        assert_eq!(
+            lexer.tokenize("mov $1 \"ok\" \"\\z\""),
+            Err(AsmParseErr::InvalidEscape(String::from("\\z"), Context::from(1, 14)))
+        );
+        assert!(matches!(lexer.tokenize("mov $1 'ab'"), Err(AsmParseErr::UnexpectedToken(_, _))));
+        assert!(matches!(lexer.tokenize("mov $1 ''"), Err(AsmParseErr::UnexpectedToken(_, _))));
+    }
+
+    #[test]
+    fn test_number_literals() {
+        let test_str = "42 -7 0x2A 0b1010 0o17 1_000 -0x10 0xffffffff";
+        let mut lexer = Lexer::new();
+        let nums: Vec<i32> = lexer.tokenize(test_str).unwrap().iter().map(|token| match token {
+            Token::NumLiteral(num, _) => *num,
+            token => panic!("expected a number, found {}", token),
+        }).collect();
+        assert_eq!(nums, vec![42, -7, 42, 10, 15, 1000, -16, -1]);
+
+        for bad in &["0x", "0b102", "1__", "12abc", "4294967296", "-2147483649"] {
+            let code = format!("mov $1 {}", bad);
+            let mut lexer = Lexer::new();
+            assert!(matches!(lexer.tokenize(&code), Err(AsmParseErr::CouldNotParse(_, _))), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_comments() {
+        let test_str = "#!/usr/bin/env verdigris run\nmov $1 5 ; load\n/* a block\n   comment */ add $1 2 $2# sum\nhlt;done";
+        let mut lexer = Lexer::new();
+        let tokens = lexer.tokenize(test_str).unwrap();
+        assert_eq!(tokens, vec![
+            Token::Opcode(Opcode::Mov, tokens[0].context()),
+            Token::Register(1, tokens[1].context()),
+            Token::NumLiteral(5, tokens[2].context()),
+            Token::Opcode(Opcode::Add, tokens[3].context()),
+            Token::Register(1, tokens[4].context()),
+            Token::NumLiteral(2, tokens[5].context()),
+            Token::Register(2, tokens[6].context()),
+            Token::Opcode(Opcode::Hlt, tokens[7].context()),
+        ]);
+        assert_eq!(tokens[3].context().line, 4);
+        assert_eq!(tokens[7].context(), Context::from(5, 4));
+
+        let string = lexer.tokenize(".string \"a ; b\"").unwrap();
+        assert_eq!(string[1], Token::StrLiteral(String::from("a ; b"), string[1].context()));
+
+        assert_eq!(lexer.tokenize("hlt\n/* open"), Err(AsmParseErr::UnexpectedEOF(Context::from(2, 1))));
+    }
+
     #[test]
     fn test_directive() {
         let test_str = ".string \"hello i am cool\"";
@@ -468,6 +786,20 @@ mod tests {
         ])
     }
 
+    #[test]
+    fn test_expressions() {
+        let test_str = ".equ SIZE (4 * (end - start))\nmov $1 SIZE";
+        let mut lexer = Lexer::new();
+        let tokens = lexer.tokenize(test_str).unwrap();
+        assert_eq!(tokens[1], Token::Ident(String::from("SIZE"), tokens[1].context()));
+        assert_eq!(tokens[2], Token::Expr(String::from("4 * (end - start)"), tokens[2].context()));
+        assert_eq!(tokens[5], Token::Ident(String::from("SIZE"), tokens[5].context()));
+        assert_eq!(tokens[5].context().line, 2);
+
+        let mut lexer = Lexer::new();
+        assert!(matches!(lexer.tokenize("mov $1 (2 + (3)"), Err(AsmParseErr::UnexpectedEOF(_))));
+    }
+
     #[test]
     fn test_unexpected() {
         let test_str = "{";
@@ -1,13 +1,21 @@
+pub mod address;
+#[allow(clippy::module_inception)]
 pub mod assembler;
+pub mod expr;
 pub mod lexer;
 pub mod parser;
+pub mod preprocessor;
 
+pub use address::Address;
 pub use assembler::Assembler;
 pub use lexer::{Lexer, Token, Context};
 pub use parser::{Parser, Operand};
+pub use preprocessor::Preprocessor;
 
 use std::fmt;
 
+use crate::vm::Opcode;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum AsmParseErr {
     UnexpectedOperand(String, Context),
@@ -19,7 +27,47 @@ pub enum AsmParseErr {
     CouldNotParse(String, Context),
     InvalidDirective(String, Context),
     InvalidOperand(Operand, Context),
+    UndefinedLabel(String, Context),
+    DuplicateLabel(String, Context),
+    InvalidAddress(String, Context),
+    InvalidExpression(String, Context),
+    NotRelocatable(String, Context),
+    InvalidEscape(String, Context),
+    IncludeNotFound(String, Context),
+    RecursiveInclude(String, Context),
+    RecursiveMacro(String, Context),
+    UnsupportedOpcode(Opcode, Context),
     InvalidOperandConversion(Token),
+    InFile(String, Box<AsmParseErr>),
+}
+
+impl AsmParseErr {
+    /// Returns where in the source the error happened.
+    pub fn context(&self) -> Context {
+        use AsmParseErr::*;
+        match self {
+            UnexpectedOperand(_, con) |
+            UnexpectedToken(_, con) |
+            IncorrectOperandNo(_, _, con) |
+            InvalidRegister(_, con) |
+            CouldNotParse(_, con) |
+            InvalidDirective(_, con) |
+            InvalidOperand(_, con) |
+            UndefinedLabel(_, con) |
+            DuplicateLabel(_, con) |
+            InvalidAddress(_, con) |
+            InvalidExpression(_, con) |
+            NotRelocatable(_, con) |
+            InvalidEscape(_, con) |
+            IncludeNotFound(_, con) |
+            RecursiveInclude(_, con) |
+            RecursiveMacro(_, con) |
+            UnsupportedOpcode(_, con) => *con,
+            UnexpectedEOF(con) | TooManyOperands(con) => *con,
+            InvalidOperandConversion(token) => token.context(),
+            InFile(_, err) => err.context(),
+        }
+    }
 }
 
 impl std::error::Error for AsmParseErr {}
@@ -64,7 +112,10 @@ impl fmt::Display for AsmParseErr {
             }
             Self::CouldNotParse(text, con) => {
                 write!(f, 
-                    "Error: could not parse `{}` as a number\nLine {} Column {}", 
+                    "Error: could not parse `{}` as a number\n\
+                    Numbers can be decimal (42), hexadecimal (0x2a), binary (0b101010), \
+                    octal (0o52) or a character ('*'), and must fit in 32 bits\n\
+                    Line {} Column {}", 
                     text, con.line, con.column
                 )
             }
@@ -80,6 +131,69 @@ impl fmt::Display for AsmParseErr {
                     op, con.line, con.column
                 )
             }
+            Self::UndefinedLabel(name, con) => {
+                write!(f,
+                    "Error: label @{} is never declared\nLine {} Column {}",
+                    name, con.line, con.column
+                )
+            }
+            Self::DuplicateLabel(name, con) => {
+                write!(f,
+                    "Error: label {} is already declared\nLine {} Column {}",
+                    name, con.line, con.column
+                )
+            }
+            Self::InvalidAddress(expr, con) => {
+                write!(f,
+                    "Error: invalid address {}\nLine {} Column {}",
+                    expr, con.line, con.column
+                )
+            }
+            Self::InvalidExpression(expr, con) => {
+                write!(f,
+                    "Error: invalid expression {}\nLine {} Column {}",
+                    expr, con.line, con.column
+                )
+            }
+            Self::NotRelocatable(op, con) => {
+                write!(f,
+                    "Error: {} uses a label address in a way that cannot be relocated\nLine {} Column {}",
+                    op, con.line, con.column
+                )
+            }
+            Self::InvalidEscape(esc, con) => {
+                write!(f,
+                    "Error: invalid escape sequence {}\nLine {} Column {}",
+                    esc, con.line, con.column
+                )
+            }
+            Self::IncludeNotFound(path, con) => {
+                write!(f,
+                    "Error: could not find included file \"{}\"\nLine {} Column {}",
+                    path, con.line, con.column
+                )
+            }
+            Self::RecursiveInclude(path, con) => {
+                write!(f,
+                    "Error: \"{}\" includes itself\nLine {} Column {}",
+                    path, con.line, con.column
+                )
+            }
+            Self::RecursiveMacro(name, con) => {
+                write!(f,
+                    "Error: macro {} expands into itself too many times\nLine {} Column {}",
+                    name, con.line, con.column
+                )
+            }
+            Self::UnsupportedOpcode(op, con) => {
+                write!(f,
+                    "Error: {} is not supported by the VM yet\nLine {} Column {}",
+                    format!("{:?}", op).to_lowercase(), con.line, con.column
+                )
+            }
+            Self::InFile(file, err) => {
+                write!(f, "{}: {}", file, err)
+            }
             Self::InvalidOperandConversion(token) => {
                 write!(f,
                     "Error: invalid operand conversion {}\nLine {} Column {}",
@@ -96,6 +210,15 @@ pub enum Directive {
     Data,
     String,
     Global,
+    Word,
+    Byte,
+    Space,
+    Align,
+    Macro,
+    Endm,
+    Include,
+    Equ,
+    Set,
 }
 
 impl Directive {
@@ -113,9 +236,41 @@ impl Directive {
             "global" => {
                 Ok(Self::Global)
             }
-            inval @ _ => {
+            "word" => {
+                Ok(Self::Word)
+            }
+            "byte" => {
+                Ok(Self::Byte)
+            }
+            "space" => {
+                Ok(Self::Space)
+            }
+            "align" => {
+                Ok(Self::Align)
+            }
+            "macro" => {
+                Ok(Self::Macro)
+            }
+            "endm" => {
+                Ok(Self::Endm)
+            }
+            "include" => {
+                Ok(Self::Include)
+            }
+            "equ" => {
+                Ok(Self::Equ)
+            }
+            "set" => {
+                Ok(Self::Set)
+            }
+            inval => {
                 Err(AsmParseErr::InvalidDirective(inval.to_string(), con))
             }
         }
     }
+
+    /// Returns true if the directive's operands are laid out in the data section.
+    pub fn is_data(&self) -> bool {
+        matches!(self, Self::Word | Self::Byte | Self::Space | Self::Align)
+    }
 }
\ No newline at end of file
@@ -10,6 +10,7 @@ use std::convert::TryFrom;
 use std::fmt;
 
 use crate::assembler::{
+    Address,
     Token, 
     AsmParseErr, 
     Context, 
@@ -19,19 +20,37 @@ use crate::vm::{Instruction, Opcode};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Parsed {
-    Instruction(Instruction),
+    Instruction(Instruction, Context),
     LabelStart(String, Context),
     LabelEnd(Context),
     Directive(AsmDir, Context),
     StrLiteral(String, Context),
+    Operand(Operand, Context),
+    Constant(AsmDir, String, Operand, Context),
+}
+
+impl Parsed {
+    pub fn context(&self) -> Context {
+        match self {
+            Parsed::Instruction(_, con) => *con,
+            Parsed::LabelStart(_, con) => *con,
+            Parsed::LabelEnd(con) => *con,
+            Parsed::Directive(_, con) => *con,
+            Parsed::StrLiteral(_, con) => *con,
+            Parsed::Operand(_, con) => *con,
+            Parsed::Constant(_, _, _, con) => *con,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Operand {
     Pointer(String),
+    Address(Address),
     Register(u8),
     LabelUse(String),
     NumLiteral(i32),
+    Expr(String),
 }
 
 impl TryFrom<Token> for Operand {
@@ -40,23 +59,26 @@ impl TryFrom<Token> for Operand {
     fn try_from(from: Token) -> Result<Self, Self::Error> {
         match from {
             Token::Pointer(ptr, _) => {
-                return Ok(Self::Pointer(ptr))
+                Ok(Self::Pointer(ptr))
             }
             Token::Register(reg, _) => {
-                return Ok(Self::Register(reg))
+                Ok(Self::Register(reg))
             }
             Token::NumLiteral(num, _) => {
-                return Ok(Self::NumLiteral(num))
+                Ok(Self::NumLiteral(num))
             }
             Token::LabelUse(name, _) => {
-                return Ok(Self::LabelUse(name))
+                Ok(Self::LabelUse(name))
+            }
+            Token::Expr(expr, _) | Token::Ident(expr, _) => {
+                Ok(Self::Expr(expr))
             }
             Token::Opcode(_, _) |
             Token::StrLiteral(_,_) |
             Token::LabelDeclStart(_, _) |
             Token::LabelDeclEnd(_) |
             Token::Directive(_,_) => {
-                return Err(AsmParseErr::InvalidOperandConversion(from.clone()))
+                Err(AsmParseErr::InvalidOperandConversion(from.clone()))
             }
         }
     }
@@ -69,6 +91,9 @@ impl fmt::Display for Operand {
             Pointer(ptr) => {
                 write!(f, "[{}]", ptr)
             }
+            Address(addr) => {
+                write!(f, "{}", addr)
+            }
             Register(reg) => {
                 write!(f, "${}", reg)
             }
@@ -78,6 +103,12 @@ impl fmt::Display for Operand {
             NumLiteral(num) => {
                 write!(f, "{}", num)
             }
+            Expr(expr) if expr.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '.') => {
+                write!(f, "{}", expr)
+            }
+            Expr(expr) => {
+                write!(f, "({})", expr)
+            }
         }
     }
 }
@@ -106,7 +137,7 @@ impl Parser {
                     parsed.push(self.create_instruction(op, con)?)
                 }
                 StrLiteral(text, con) => {
-                    if let Some(&Parsed::Directive(AsmDir::String, _)) = parsed.last() {
+                    if !matches!(parsed.last(), Some(&Parsed::Directive(AsmDir::String, _))) {
                         return Err(AsmParseErr::UnexpectedToken(
                             format!("str: {}\nString literals should always follow a .string directive.", 
                                 text
@@ -122,11 +153,32 @@ impl Parser {
                 LabelDeclEnd(con) => {
                     parsed.push(Parsed::LabelEnd(con));
                 }
+                Directive(dir @ AsmDir::Equ, con) | Directive(dir @ AsmDir::Set, con) => {
+                    parsed.push(self.create_constant(dir, con)?);
+                }
                 Directive(dir, con) => {
                     parsed.push(Parsed::Directive(dir, con));
                 }
-                _ => {
-                    panic!("Operand tokens should not appear in the main parse fn")
+                Ident(name, con) => {
+                    // a bare name is a constant, or a macro that was never defined
+                    match parsed.last() {
+                        Some(Parsed::Directive(_, _)) | Some(Parsed::Operand(_, _)) => {
+                            parsed.push(Parsed::Operand(Operand::Expr(name), con));
+                        }
+                        _ => return Err(AsmParseErr::UnexpectedToken(name, con)),
+                    }
+                }
+                token => {
+                    // operands outside of an instruction are arguments to a directive
+                    let con = token.context();
+                    match parsed.last() {
+                        Some(Parsed::Directive(_, _)) | Some(Parsed::Operand(_, _)) => {
+                            parsed.push(Parsed::Operand(Operand::try_from(token)?, con));
+                        }
+                        _ => {
+                            return Err(AsmParseErr::UnexpectedOperand(token.to_string(), con))
+                        }
+                    }
                 }
             }
         }
@@ -139,7 +191,7 @@ impl Parser {
         use Opcode::*;
 
         let inst: Instruction;
-        let operands = self.get_operands()?;
+        let operands = self.get_operands(con)?;
         let len = if operands.len() > 3 {
             return Err(TooManyOperands(con))
         } else {
@@ -150,6 +202,9 @@ impl Parser {
 
         match op {
             Hlt => {
+                if len != 0 {
+                    return Err(IncorrectOperandNo(0, len, con))
+                }
                 inst = Instruction::from_parsed(Hlt, (None, None, None));
             }
             Mov => {
@@ -169,16 +224,20 @@ impl Parser {
                 // checking operand 2
                 if let Operand::NumLiteral(num) = &operands[1] {
                     final_ops.1 = Some(Operand::NumLiteral(*num));
+                } else if let Operand::Expr(expr) = &operands[1] {
+                    final_ops.1 = Some(Operand::Expr(expr.clone()));
                 } else if let Operand::Pointer(ptr) = &operands[1] {
                     final_ops.1 = Some(Operand::Pointer(ptr.clone()));
                 } else if let Operand::Register(reg) = &operands[1] {
                     final_ops.1 = Some(Operand::Register(*reg))
+                } else if let Operand::LabelUse(lab) = &operands[1] {
+                    final_ops.1 = Some(Operand::LabelUse(lab.clone()));
                 } else {
                     return Err(InvalidOperand(operands[1].clone(), con))
                 }
                 inst = Instruction::from_parsed(Mov, final_ops);
             }
-            Jmp => {
+            op @ Jmp | op @ Jeq | op @ Jne => {
                 if len != 1 {
                     return Err(IncorrectOperandNo(1, len, con))
                 }
@@ -186,6 +245,8 @@ impl Parser {
                 // checking operand 1
                 if let Operand::NumLiteral(num) = &operands[0] {
                     final_ops.0 = Some(Operand::NumLiteral(*num));
+                } else if let Operand::Expr(expr) = &operands[0] {
+                    final_ops.0 = Some(Operand::Expr(expr.clone()));
                 } else if let Operand::Pointer(ptr) = &operands[0] {
                     final_ops.0 = Some(Operand::Pointer(ptr.clone()));
                 } else if let Operand::LabelUse(lab) = &operands[0] {
@@ -195,7 +256,7 @@ impl Parser {
                 } else {
                     return Err(InvalidOperand(operands[0].clone(), con))
                 }
-                inst = Instruction::from_parsed(Jmp, final_ops);
+                inst = Instruction::from_parsed(op, final_ops);
             }
             op @ Jmpf | op @ Jmpb => {
                 if len != 1 {
@@ -204,6 +265,8 @@ impl Parser {
 
                 if let Operand::NumLiteral(num) = &operands[0] {
                     final_ops.0 = Some(Operand::NumLiteral(*num));
+                } else if let Operand::Expr(expr) = &operands[0] {
+                    final_ops.0 = Some(Operand::Expr(expr.clone()));
                 } else if let Operand::Register(reg) = &operands[0] {
                     final_ops.0 = Some(Operand::Register(*reg));
                 } else {
@@ -219,38 +282,204 @@ impl Parser {
                 
                 if let Operand::NumLiteral(num) = &operands[0] {
                     final_ops.0 = Some(Operand::NumLiteral(*num));
+                } else if let Operand::Expr(expr) = &operands[0] {
+                    final_ops.0 = Some(Operand::Expr(expr.clone()));
                 } else if let Operand::Register(reg) = &operands[0] {
                     final_ops.0 = Some(Operand::Register(*reg));
+                } else if let Operand::LabelUse(lab) = &operands[0] {
+                    final_ops.0 = Some(Operand::LabelUse(lab.clone()));
                 } else {
                     return Err(InvalidOperand(operands[0].clone(), con))
                 }
 
                 if let Operand::NumLiteral(num) = &operands[1] {
                     final_ops.1 = Some(Operand::NumLiteral(*num));
+                } else if let Operand::Expr(expr) = &operands[1] {
+                    final_ops.1 = Some(Operand::Expr(expr.clone()));
                 } else if let Operand::Register(reg) = &operands[1] {
                     final_ops.1 = Some(Operand::Register(*reg));
+                } else if let Operand::LabelUse(lab) = &operands[1] {
+                    final_ops.1 = Some(Operand::LabelUse(lab.clone()));
                 } else {
                     return Err(InvalidOperand(operands[1].clone(), con))
                 }
 
                 inst = Instruction::from_parsed(op, final_ops);
             } 
-            Push | Pop | Call | Ret => {
-                unimplemented!("Calling conventions not yet specified")
+            op @ Inc | op @ Dec | op @ Not => {
+                if len != 2 {
+                    return Err(IncorrectOperandNo(2, len, con))
+                }
+
+                if let Operand::NumLiteral(num) = &operands[0] {
+                    final_ops.0 = Some(Operand::NumLiteral(*num));
+                } else if let Operand::Expr(expr) = &operands[0] {
+                    final_ops.0 = Some(Operand::Expr(expr.clone()));
+                } else if let Operand::Register(reg) = &operands[0] {
+                    final_ops.0 = Some(Operand::Register(*reg));
+                } else {
+                    return Err(InvalidOperand(operands[0].clone(), con))
+                }
+
+                if let Operand::Register(reg) = &operands[1] {
+                    final_ops.1 = Some(Operand::Register(*reg));
+                } else {
+                    return Err(InvalidOperand(operands[1].clone(), con))
+                }
+
+                inst = Instruction::from_parsed(op, final_ops);
+            }
+            op @ Add | op @ Sub | op @ Mul | op @ Div |
+            op @ And | op @ Or  | op @ Xor | op @ Bsl | op @ Bsr => {
+                if len != 3 {
+                    return Err(IncorrectOperandNo(3, len, con))
+                }
+
+                if let Operand::NumLiteral(num) = &operands[0] {
+                    final_ops.0 = Some(Operand::NumLiteral(*num));
+                } else if let Operand::Expr(expr) = &operands[0] {
+                    final_ops.0 = Some(Operand::Expr(expr.clone()));
+                } else if let Operand::Register(reg) = &operands[0] {
+                    final_ops.0 = Some(Operand::Register(*reg));
+                } else {
+                    return Err(InvalidOperand(operands[0].clone(), con))
+                }
+
+                if let Operand::NumLiteral(num) = &operands[1] {
+                    final_ops.1 = Some(Operand::NumLiteral(*num));
+                } else if let Operand::Expr(expr) = &operands[1] {
+                    final_ops.1 = Some(Operand::Expr(expr.clone()));
+                } else if let Operand::Register(reg) = &operands[1] {
+                    final_ops.1 = Some(Operand::Register(*reg));
+                } else {
+                    return Err(InvalidOperand(operands[1].clone(), con))
+                }
+
+                if let Operand::Register(reg) = &operands[2] {
+                    final_ops.2 = Some(Operand::Register(*reg));
+                } else {
+                    return Err(InvalidOperand(operands[2].clone(), con))
+                }
+
+                inst = Instruction::from_parsed(op, final_ops);
+            }
+            Push => {
+                if len != 1 {
+                    return Err(IncorrectOperandNo(1, len, con))
+                }
+
+                if let Operand::NumLiteral(num) = &operands[0] {
+                    final_ops.0 = Some(Operand::NumLiteral(*num));
+                } else if let Operand::Expr(expr) = &operands[0] {
+                    final_ops.0 = Some(Operand::Expr(expr.clone()));
+                } else if let Operand::Register(reg) = &operands[0] {
+                    final_ops.0 = Some(Operand::Register(*reg));
+                } else if let Operand::LabelUse(lab) = &operands[0] {
+                    final_ops.0 = Some(Operand::LabelUse(lab.clone()));
+                } else {
+                    return Err(InvalidOperand(operands[0].clone(), con))
+                }
+
+                inst = Instruction::from_parsed(Push, final_ops);
+            }
+            Pop => {
+                if len != 1 {
+                    return Err(IncorrectOperandNo(1, len, con))
+                }
+
+                if let Operand::Register(reg) = &operands[0] {
+                    final_ops.0 = Some(Operand::Register(*reg));
+                } else {
+                    return Err(InvalidOperand(operands[0].clone(), con))
+                }
+
+                inst = Instruction::from_parsed(Pop, final_ops);
+            }
+            Call => {
+                if len != 1 {
+                    return Err(IncorrectOperandNo(1, len, con))
+                }
+
+                if let Operand::NumLiteral(num) = &operands[0] {
+                    final_ops.0 = Some(Operand::NumLiteral(*num));
+                } else if let Operand::Expr(expr) = &operands[0] {
+                    final_ops.0 = Some(Operand::Expr(expr.clone()));
+                } else if let Operand::Pointer(ptr) = &operands[0] {
+                    final_ops.0 = Some(Operand::Pointer(ptr.clone()));
+                } else if let Operand::LabelUse(lab) = &operands[0] {
+                    final_ops.0 = Some(Operand::LabelUse(lab.clone()));
+                } else if let Operand::Register(reg) = &operands[0] {
+                    final_ops.0 = Some(Operand::Register(*reg));
+                } else {
+                    return Err(InvalidOperand(operands[0].clone(), con))
+                }
+
+                inst = Instruction::from_parsed(Call, final_ops);
             }
-            _ => {
-                unimplemented!("Other opcodes not yet implemented")
+            Ret => {
+                if len != 0 {
+                    return Err(IncorrectOperandNo(0, len, con))
+                }
+                inst = Instruction::from_parsed(Ret, (None, None, None));
+            }
+            Aloc => {
+                if len != 1 {
+                    return Err(IncorrectOperandNo(1, len, con))
+                }
+
+                if let Operand::NumLiteral(num) = &operands[0] {
+                    final_ops.0 = Some(Operand::NumLiteral(*num));
+                } else if let Operand::Expr(expr) = &operands[0] {
+                    final_ops.0 = Some(Operand::Expr(expr.clone()));
+                } else if let Operand::Register(reg) = &operands[0] {
+                    final_ops.0 = Some(Operand::Register(*reg));
+                } else {
+                    return Err(InvalidOperand(operands[0].clone(), con))
+                }
+
+                inst = Instruction::from_parsed(Aloc, final_ops);
+            }
+            op => {
+                return Err(UnsupportedOpcode(op, con))
             }
         }
-        Ok(Parsed::Instruction(inst))
+        Ok(Parsed::Instruction(inst, con))
     }
 
-    fn get_operands(&mut self) -> Result<Vec<Operand>, AsmParseErr> {
+    /// Parses a `.equ` or `.set` directive: a name followed by its value.
+    fn create_constant(&mut self, dir: AsmDir, con: Context) -> Result<Parsed, AsmParseErr> {
+        let name = match self.tokens.next() {
+            Some(Token::Ident(name, _)) => name,
+            Some(token) => {
+                return Err(AsmParseErr::UnexpectedToken(
+                    format!("{}\nA constant needs a name that is not an opcode.", token),
+                    token.context()
+                ))
+            }
+            None => return Err(AsmParseErr::UnexpectedEOF(con)),
+        };
+        let value = match self.tokens.next() {
+            Some(token) if token.is_operand() || matches!(token, Token::Ident(_, _)) => {
+                Operand::try_from(token)?
+            }
+            Some(token) => {
+                return Err(AsmParseErr::UnexpectedToken(token.to_string(), token.context()))
+            }
+            None => return Err(AsmParseErr::UnexpectedEOF(con)),
+        };
+        Ok(Parsed::Constant(dir, name, value, con))
+    }
+
+    /// Collects the operands following an instruction. Constant names
+    /// are only taken as operands on the same line as the instruction.
+    fn get_operands(&mut self, con: Context) -> Result<Vec<Operand>, AsmParseErr> {
         let mut operands: Vec<Operand> = Vec::new();
         loop {
             let peek = self.tokens.peek();
             if let Some(peek) = peek {
-                if !peek.is_operand() {
+                let same_line = peek.context().line == con.line && peek.context().file == con.file;
+                let is_constant = matches!(peek, Token::Ident(_, _)) && same_line;
+                if !peek.is_operand() && !is_constant {
                     return Ok(operands)
                 }
             } else {
@@ -266,6 +495,12 @@ impl Parser {
     }
 }
 
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -282,14 +517,17 @@ mod tests {
 
         let inst = Instruction::from_parsed(Opcode::Hlt, (None, None, None));
 
-        assert_eq!(parsed, vec![Parsed::Instruction(inst)])
+        assert_eq!(parsed, vec![Parsed::Instruction(inst, parsed[0].context())]);
+
+        let tokens = Lexer::new().tokenize("hlt 5").unwrap();
+        assert!(matches!(Parser::new().parse(tokens), Err(AsmParseErr::IncorrectOperandNo(0, 1, _))));
     }
 
     #[test]
     fn test_mov_opcode_parsing() {
         let test_code1 = "mov $2 [500]";
         let test_code2 = "mov $3 $23 mov $5 69";
-        let fail_code1 = "mov [4] @label";
+        let fail_code1 = "mov 4 @label";
 
         let mut lexer = Lexer::new();
         let tokens1 = lexer.tokenize(test_code1).unwrap();
@@ -328,10 +566,10 @@ mod tests {
             )
         );
 
-        assert_eq!(parsed1, vec![Parsed::Instruction(cmp_inst1)]);
+        assert_eq!(parsed1, vec![Parsed::Instruction(cmp_inst1, parsed1[0].context())]);
         assert_eq!(parsed2, vec![
-            Parsed::Instruction(cmp_inst2_1),
-            Parsed::Instruction(cmp_inst2_2),
+            Parsed::Instruction(cmp_inst2_1, parsed2[0].context()),
+            Parsed::Instruction(cmp_inst2_2, parsed2[1].context()),
         ]);
         assert!(parsed3.is_err())
     }
@@ -391,10 +629,10 @@ mod tests {
             )
         );
 
-        assert_eq!(parsed_lit, vec![Parsed::Instruction(inst_lit)]);
-        assert_eq!(parsed_ptr, vec![Parsed::Instruction(inst_ptr)]);
-        assert_eq!(parsed_lab, vec![Parsed::Instruction(inst_lab)]);
-        assert_eq!(parsed_reg, vec![Parsed::Instruction(inst_reg)]);
+        assert_eq!(parsed_lit, vec![Parsed::Instruction(inst_lit, parsed_lit[0].context())]);
+        assert_eq!(parsed_ptr, vec![Parsed::Instruction(inst_ptr, parsed_ptr[0].context())]);
+        assert_eq!(parsed_lab, vec![Parsed::Instruction(inst_lab, parsed_lab[0].context())]);
+        assert_eq!(parsed_reg, vec![Parsed::Instruction(inst_reg, parsed_reg[0].context())]);
     }
 
     #[test]
@@ -459,10 +697,10 @@ mod tests {
             )
         );
 
-        assert_eq!(p_jmpf_lit, vec![Parsed::Instruction(inst_jmpf_lit)]);
-        assert_eq!(p_jmpf_reg, vec![Parsed::Instruction(inst_jmpf_reg)]);
-        assert_eq!(p_jmpb_lit, vec![Parsed::Instruction(inst_jmpb_lit)]);
-        assert_eq!(p_jmpb_reg, vec![Parsed::Instruction(inst_jmpb_reg)]);
+        assert_eq!(p_jmpf_lit, vec![Parsed::Instruction(inst_jmpf_lit, p_jmpf_lit[0].context())]);
+        assert_eq!(p_jmpf_reg, vec![Parsed::Instruction(inst_jmpf_reg, p_jmpf_reg[0].context())]);
+        assert_eq!(p_jmpb_lit, vec![Parsed::Instruction(inst_jmpb_lit, p_jmpb_lit[0].context())]);
+        assert_eq!(p_jmpb_reg, vec![Parsed::Instruction(inst_jmpb_reg, p_jmpb_reg[0].context())]);
         assert!(p_jmpf_err.is_err());
         assert!(p_jmpb_err.is_err());
     }
@@ -489,7 +727,125 @@ mod tests {
             )
         );
 
-        assert_eq!(parsed, vec![Parsed::Instruction(inst)]);
+        assert_eq!(parsed, vec![Parsed::Instruction(inst, parsed[0].context())]);
         assert!(parsed_err.is_err());
     }
+
+    #[test]
+    fn test_arithmetic_parsing() {
+        let test_code = "add $1 5 $2 not $3 $4";
+        let test_err1 = "add $1 5 7";
+        let test_err2 = "inc $1";
+
+        let mut lexer = Lexer::new();
+        let tokens = lexer.tokenize(test_code).unwrap();
+        let tokens_err1 = lexer.tokenize(test_err1).unwrap();
+        let tokens_err2 = lexer.tokenize(test_err2).unwrap();
+
+        let mut parser = Parser::new();
+        let parsed = parser.parse(tokens).unwrap();
+        let parsed_err1 = parser.parse(tokens_err1);
+        let parsed_err2 = parser.parse(tokens_err2);
+
+        let inst_add = Instruction::from_parsed(
+            Opcode::Add,
+            (
+                Some(Operand::Register(1)),
+                Some(Operand::NumLiteral(5)),
+                Some(Operand::Register(2)),
+            )
+        );
+
+        let inst_not = Instruction::from_parsed(
+            Opcode::Not,
+            (
+                Some(Operand::Register(3)),
+                Some(Operand::Register(4)),
+                None,
+            )
+        );
+
+        assert_eq!(parsed, vec![
+            Parsed::Instruction(inst_add, parsed[0].context()),
+            Parsed::Instruction(inst_not, parsed[1].context()),
+        ]);
+        assert!(parsed_err1.is_err());
+        assert!(parsed_err2.is_err());
+    }
+
+    #[test]
+    fn test_call_and_stack_parsing() {
+        let test_code = "push $1 call @func pop $1 ret";
+        let test_err1 = "pop 5";
+        let test_err2 = "ret $1";
+
+        let mut lexer = Lexer::new();
+        let tokens = lexer.tokenize(test_code).unwrap();
+        let tokens_err1 = lexer.tokenize(test_err1).unwrap();
+        let tokens_err2 = lexer.tokenize(test_err2).unwrap();
+
+        let mut parser = Parser::new();
+        let parsed = parser.parse(tokens).unwrap();
+        let parsed_err1 = parser.parse(tokens_err1);
+        let parsed_err2 = parser.parse(tokens_err2);
+
+        assert_eq!(parsed, vec![
+            Parsed::Instruction(Instruction::from_parsed(
+                Opcode::Push, (Some(Operand::Register(1)), None, None)
+            ), parsed[0].context()),
+            Parsed::Instruction(Instruction::from_parsed(
+                Opcode::Call, (Some(Operand::LabelUse(String::from("func"))), None, None)
+            ), parsed[1].context()),
+            Parsed::Instruction(Instruction::from_parsed(
+                Opcode::Pop, (Some(Operand::Register(1)), None, None)
+            ), parsed[2].context()),
+            Parsed::Instruction(Instruction::from_parsed(
+                Opcode::Ret, (None, None, None)
+            ), parsed[3].context()),
+        ]);
+        assert!(parsed_err1.is_err());
+        assert!(parsed_err2.is_err());
+    }
+
+    #[test]
+    fn test_memory_and_io_parsing() {
+        let mut lexer = Lexer::new();
+        let mut parser = Parser::new();
+        let parsed = parser.parse(lexer.tokenize("aloc 8 aloc $2").unwrap()).unwrap();
+        assert_eq!(parsed, vec![
+            Parsed::Instruction(Instruction::from_parsed(
+                Opcode::Aloc, (Some(Operand::NumLiteral(8)), None, None)
+            ), parsed[0].context()),
+            Parsed::Instruction(Instruction::from_parsed(
+                Opcode::Aloc, (Some(Operand::Register(2)), None, None)
+            ), parsed[1].context()),
+        ]);
+        assert!(parser.parse(lexer.tokenize("aloc [8]").unwrap()).is_err());
+
+        for code in &["dalc $1", "prt $1", "open $1", "clse $1", "read $1", "wrt $1 $2"] {
+            let err = parser.parse(Lexer::new().tokenize(code).unwrap()).unwrap_err();
+            assert!(matches!(err, AsmParseErr::UnsupportedOpcode(_, con) if con.line == 1), "{}", code);
+        }
+    }
+
+    #[test]
+    fn test_directive_arguments() {
+        let test_code = ".global @main @other";
+        let test_err = "main: { 5 }";
+
+        let mut lexer = Lexer::new();
+        let tokens = lexer.tokenize(test_code).unwrap();
+        let tokens_err = lexer.tokenize(test_err).unwrap();
+
+        let mut parser = Parser::new();
+        let parsed = parser.parse(tokens).unwrap();
+        let parsed_err = parser.parse(tokens_err);
+
+        assert_eq!(parsed, vec![
+            Parsed::Directive(AsmDir::Global, parsed[0].context()),
+            Parsed::Operand(Operand::LabelUse(String::from("main")), parsed[1].context()),
+            Parsed::Operand(Operand::LabelUse(String::from("other")), parsed[2].context()),
+        ]);
+        assert!(matches!(parsed_err, Err(AsmParseErr::UnexpectedOperand(_, _))));
+    }
 }
\ No newline at end of file
@@ -0,0 +1,420 @@
+//! The Preprocessor expands macros and included files in a token stream
+//! before it is handed to the Parser.
+//!
+//! A macro is defined with `.macro`, followed by its name and parameters
+//! on the same line, then its body and `.endm`:
+//!
+//! ```text
+//! .macro save reg
+//!     push reg
+//! .endm
+//!
+//! save $16
+//! ```
+//!
+//! Parameters in the body, including inside pointers and expressions,
+//! are replaced with the operands given on the same line as the macro's
+//! name. Labels declared inside a macro are local to each expansion.
+//!
+//! `.include "file.vasm"` is replaced with the tokens of that file. Files
+//! are looked up relative to the including file first, then in each of
+//! the include paths.
+
+use std::collections::HashMap;
+use std::fs;
+use std::iter::{self, Peekable};
+use std::path::{Path, PathBuf};
+use std::vec::IntoIter;
+
+use crate::assembler::{AsmParseErr, Context, Directive, Lexer, Token};
+
+/// How deeply macros and included files can nest.
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, Clone)]
+pub struct Preprocessor {
+    include_paths: Vec<PathBuf>,
+    files: Vec<PathBuf>,
+    including: Vec<PathBuf>,
+    macros: HashMap<String, Macro>,
+    expansions: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+}
+
+impl Preprocessor {
+    pub fn new() -> Self {
+        Self {
+            include_paths: Vec::new(),
+            files: Vec::new(),
+            including: Vec::new(),
+            macros: HashMap::new(),
+            expansions: 0,
+        }
+    }
+
+    /// Adds a directory to search for included files.
+    pub fn add_include_path<P: Into<PathBuf>>(&mut self, path: P) {
+        self.include_paths.push(path.into());
+    }
+
+    /// The files read so far, indexed by `Context::file`.
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// Tokenizes a source file and expands its macros and includes.
+    ///
+    /// Includes are resolved relative to `path`, which may be empty
+    /// for source that did not come from a file.
+    pub fn process(&mut self, source: &str, path: &Path) -> Result<Vec<Token>, AsmParseErr> {
+        self.read(source, path, 0)
+    }
+
+    fn read(&mut self, source: &str, path: &Path, depth: usize) -> Result<Vec<Token>, AsmParseErr> {
+        let mut lexer = Lexer::new();
+        lexer.set_file(self.files.len());
+        self.files.push(path.to_path_buf());
+        let tokens = lexer.tokenize(source)?;
+
+        let canonical = path.canonicalize().ok();
+        if let Some(canonical) = &canonical {
+            self.including.push(canonical.clone());
+        }
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        let expanded = self.expand(tokens, dir, depth);
+        if canonical.is_some() {
+            self.including.pop();
+        }
+        expanded
+    }
+
+    fn expand(&mut self, tokens: Vec<Token>, dir: &Path, depth: usize) -> Result<Vec<Token>, AsmParseErr> {
+        let mut output = Vec::new();
+        let mut tokens = tokens.into_iter().peekable();
+
+        while let Some(token) = tokens.next() {
+            match token {
+                Token::Directive(Directive::Include, con) => {
+                    let name = match tokens.next() {
+                        Some(Token::StrLiteral(name, _)) => name,
+                        Some(token) => {
+                            return Err(AsmParseErr::UnexpectedToken(
+                                format!("{}\n.include should be followed by a file name in quotes.", token),
+                                token.context()
+                            ))
+                        }
+                        None => return Err(AsmParseErr::UnexpectedEOF(con)),
+                    };
+                    output.extend(self.include(&name, dir, con, depth)?);
+                }
+                Token::Directive(Directive::Macro, con) => {
+                    self.define(&mut tokens, con)?;
+                }
+                Token::Directive(Directive::Endm, con) => {
+                    return Err(AsmParseErr::UnexpectedToken(String::from(".endm"), con))
+                }
+                Token::Ident(name, con) if self.macros.contains_key(&name) => {
+                    let mut args = Vec::new();
+                    while let Some(arg) = tokens.next_if(|t| {
+                        (t.is_operand() || matches!(t, Token::Ident(_, _))) && same_line(t.context(), con)
+                    }) {
+                        args.push(arg);
+                    }
+                    if depth >= MAX_DEPTH {
+                        return Err(AsmParseErr::RecursiveMacro(name, con))
+                    }
+                    let body = self.instantiate(&name, args, con)?;
+                    output.extend(self.expand(body, dir, depth + 1)?);
+                }
+                token => output.push(token),
+            }
+        }
+
+        Ok(output)
+    }
+
+    fn include(&mut self, name: &str, dir: &Path, con: Context, depth: usize) -> Result<Vec<Token>, AsmParseErr> {
+        let not_found = || AsmParseErr::IncludeNotFound(name.to_string(), con);
+        let path = iter::once(dir.join(name))
+            .chain(self.include_paths.iter().map(|inc| inc.join(name)))
+            .find(|path| path.is_file())
+            .ok_or_else(not_found)?;
+
+        let canonical = path.canonicalize().map_err(|_| not_found())?;
+        if self.including.contains(&canonical) || depth >= MAX_DEPTH {
+            return Err(AsmParseErr::RecursiveInclude(name.to_string(), con))
+        }
+        let source = fs::read_to_string(&path).map_err(|_| not_found())?;
+        self.read(&source, &path, depth + 1)
+    }
+
+    /// Reads a macro definition up to its `.endm`.
+    fn define(&mut self, tokens: &mut Peekable<IntoIter<Token>>, con: Context) -> Result<(), AsmParseErr> {
+        let name = match tokens.next() {
+            Some(Token::Ident(name, name_con)) if same_line(name_con, con) => name,
+            Some(token) => {
+                return Err(AsmParseErr::UnexpectedToken(
+                    format!("{}\nA macro needs a name that is not an opcode.", token),
+                    token.context()
+                ))
+            }
+            None => return Err(AsmParseErr::UnexpectedEOF(con)),
+        };
+
+        let mut params = Vec::new();
+        while let Some(Token::Ident(param, _)) = tokens.next_if(|t| {
+            matches!(t, Token::Ident(_, _)) && same_line(t.context(), con)
+        }) {
+            params.push(param);
+        }
+
+        let mut body = Vec::new();
+        loop {
+            match tokens.next() {
+                Some(Token::Directive(Directive::Endm, _)) => break,
+                Some(Token::Directive(Directive::Macro, inner)) => {
+                    return Err(AsmParseErr::UnexpectedToken(
+                        String::from(".macro\nMacros cannot be defined inside other macros."),
+                        inner
+                    ))
+                }
+                Some(token) => body.push(token),
+                None => return Err(AsmParseErr::UnexpectedEOF(con)),
+            }
+        }
+
+        self.macros.insert(name, Macro {
+            params,
+            body,
+        });
+        Ok(())
+    }
+
+    /// Substitutes a macro's arguments into its body and renames its local labels.
+    fn instantiate(&mut self, name: &str, args: Vec<Token>, con: Context) -> Result<Vec<Token>, AsmParseErr> {
+        let mac = self.macros.get(name)
+            .cloned()
+            .ok_or_else(|| AsmParseErr::UnexpectedToken(name.to_string(), con))?;
+        if args.len() != mac.params.len() {
+            return Err(AsmParseErr::IncorrectOperandNo(mac.params.len() as u8, args.len() as u8, con))
+        }
+
+        self.expansions += 1;
+        let locals: Vec<&String> = mac.body.iter()
+            .filter_map(|token| match token {
+                Token::LabelDeclStart(label, _) => Some(label),
+                _ => None,
+            })
+            .collect();
+        let arg = |word: &str| mac.params.iter().position(|param| param == word).map(|i| &args[i]);
+        let local = |word: &str| {
+            if locals.iter().any(|label| *label == word) {
+                Some(format!("{}.{}", word, self.expansions))
+            } else {
+                None
+            }
+        };
+
+        let body = mac.body.iter().map(|token| match token {
+            // the argument takes the place of the parameter, so that the
+            // parser sees it on the same line as the rest of the body
+            Token::Ident(word, c) | Token::LabelUse(word, c) if arg(word).is_some() => {
+                arg(word).unwrap().clone().with_context(*c)
+            }
+            Token::LabelDeclStart(label, c) => {
+                Token::LabelDeclStart(local(label).unwrap_or_else(|| label.clone()), *c)
+            }
+            Token::LabelUse(label, c) => {
+                Token::LabelUse(local(label).unwrap_or_else(|| label.clone()), *c)
+            }
+            Token::Pointer(text, c) => {
+                let text = substitute_words(text, |word| {
+                    arg(word).map(|arg| arg.to_string()).or_else(|| local(word))
+                });
+                Token::Pointer(text, *c)
+            }
+            Token::Expr(text, c) => {
+                let text = substitute_words(text, |word| {
+                    arg(word).map(|arg| arg.to_string()).or_else(|| local(word))
+                });
+                Token::Expr(text, *c)
+            }
+            token => token.clone(),
+        }).collect();
+
+        Ok(body)
+    }
+}
+
+impl Default for Preprocessor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn same_line(a: Context, b: Context) -> bool {
+    a.line == b.line && a.file == b.file
+}
+
+/// Replaces whole words in a pointer or constant expression.
+fn substitute_words<F>(text: &str, replace: F) -> String
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut output = String::new();
+    let mut word = String::new();
+    for c in text.chars().chain(iter::once(' ')) {
+        if c.is_alphanumeric() || c == '_' || c == '.' {
+            word.push(c);
+            continue
+        }
+        if !word.is_empty() {
+            output.push_str(&replace(&word).unwrap_or_else(|| word.clone()));
+            word.clear();
+        }
+        output.push(c);
+    }
+    output.pop();
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    use crate::assembler::Assembler;
+    use crate::vm::VM;
+
+    /// A directory of source files for one test, removed when it is dropped
+    /// so that nothing is left behind even if the test fails.
+    struct Fixtures(PathBuf);
+
+    impl Fixtures {
+        fn join<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+            self.0.join(path)
+        }
+    }
+
+    impl Drop for Fixtures {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Writes files into a fresh directory under the system temp directory.
+    fn write_files(test: &str, files: &[(&str, &str)]) -> Fixtures {
+        let dir = env::temp_dir().join(format!("vdg-preprocessor-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for (name, contents) in files {
+            fs::write(dir.join(name), contents).unwrap();
+        }
+        Fixtures(dir)
+    }
+
+    #[test]
+    fn test_macro_expansion() {
+        let test_code = "\
+            .macro count_to reg limit\n\
+            mov reg 0\n\
+            loop: { inc reg reg\n\
+            lt reg limit\n\
+            jeq @loop }\n\
+            .endm\n\
+            count_to $1 3\n\
+            mov $3 [total]\n\
+            count_to $2 $3\n\
+            hlt\n\
+            .data\n\
+            total: { .word 5 }";
+        let mut assembler = Assembler::new();
+        let image = assembler.assemble_source(test_code).unwrap();
+        assert!(assembler.symbols().contains_key("loop.1"));
+        assert!(assembler.symbols().contains_key("loop.2"));
+
+        let mut test_vm = VM::new(image);
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.test_register(1).unwrap(), 3);
+        assert_eq!(test_vm.test_register(2).unwrap(), 5);
+    }
+
+    #[test]
+    fn test_macro_errors() {
+        let mut assembler = Assembler::new();
+        let wrong_args = assembler.assemble_source(".macro two a b\nhlt\n.endm\ntwo $1");
+        assert!(matches!(wrong_args, Err(AsmParseErr::IncorrectOperandNo(2, 1, _))));
+
+        let unknown = assembler.assemble_source("nothing $1");
+        assert_eq!(unknown, Err(AsmParseErr::UnexpectedToken(String::from("nothing"), Context::from(1, 8))));
+
+        let unclosed = assembler.assemble_source(".macro unclosed\nhlt");
+        assert!(matches!(unclosed, Err(AsmParseErr::UnexpectedEOF(_))));
+
+        let recursive = assembler.assemble_source(".macro again\nagain\n.endm\nagain");
+        assert!(matches!(recursive, Err(AsmParseErr::RecursiveMacro(_, _))));
+    }
+
+    #[test]
+    fn test_constant_macro_arguments() {
+        let test_code = "\
+            .equ N 3\n\
+            .macro setn reg val\n\
+            mov reg val\n\
+            .endm\n\
+            setn $0 N\n\
+            setn $1 (N * 2)\n\
+            hlt";
+        let mut test_vm = VM::new(Assembler::new().assemble_source(test_code).unwrap());
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.test_register(0).unwrap(), 3);
+        assert_eq!(test_vm.test_register(1).unwrap(), 6);
+    }
+
+    #[test]
+    fn test_include() {
+        let dir = write_files("include", &[
+            ("main.vasm", ".include \"lib/util.vasm\"\nsave $16\nhlt"),
+        ]);
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::write(dir.join("lib/util.vasm"), ".macro save reg\npush reg\n.endm").unwrap();
+
+        let main = dir.join("main.vasm");
+        let mut preprocessor = Preprocessor::new();
+        let tokens = preprocessor.process(&fs::read_to_string(&main).unwrap(), &main).unwrap();
+        assert_eq!(tokens.len(), 3);
+        assert_eq!(preprocessor.files(), &[main.clone(), dir.join("lib/util.vasm")]);
+
+        let mut preprocessor = Preprocessor::new();
+        preprocessor.add_include_path(dir.join("lib"));
+        let tokens = preprocessor.process(".include \"util.vasm\"\nsave $1", Path::new("")).unwrap();
+        assert_eq!(tokens[1], Token::Register(1, tokens[1].context()));
+    }
+
+    #[test]
+    fn test_include_errors() {
+        let dir = write_files("include-errors", &[
+            ("a.vasm", "hlt\n.include \"b.vasm\""),
+            ("b.vasm", ".include \"a.vasm\""),
+            ("bad.vasm", "hlt\nmov $1 @nowhere"),
+        ]);
+
+        let a = dir.join("a.vasm");
+        let mut assembler = Assembler::new();
+        let cycle = assembler.assemble_file(&a, &fs::read_to_string(&a).unwrap());
+        assert!(matches!(cycle, Err(AsmParseErr::InFile(_, ref err)) if matches!(**err, AsmParseErr::RecursiveInclude(_, _))));
+
+        let missing = assembler.assemble_source(".include \"missing.vasm\"");
+        assert!(matches!(missing, Err(AsmParseErr::IncludeNotFound(_, _))));
+
+        let source = format!(".include \"{}\"", dir.join("bad.vasm").display());
+        let err = assembler.assemble_source(&source).unwrap_err();
+        assert_eq!(err.context().line, 2);
+        assert!(err.to_string().starts_with(&format!("{}: Error:", dir.join("bad.vasm").display())));
+    }
+}
@@ -0,0 +1,164 @@
+//! The command-line assembler for Verdigris assembly.
+//!
+//! Assembles a `.vasm` file into an Oxidizer object file.
+//! Symbols declared with `.global` but not defined are left for `vdg-ld`.
+//! Exit codes follow the BSD sysexits convention.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+use vdg_oxidizer::assembler::Assembler;
+
+const USAGE: &str = "Usage: vdg-asm <input.vasm> [-o <output>] [-I <dir>]... [--listing]";
+
+const EX_USAGE: i32 = 64;
+const EX_DATAERR: i32 = 65;
+const EX_NOINPUT: i32 = 66;
+const EX_CANTCREAT: i32 = 73;
+
+#[derive(Debug, Clone, PartialEq)]
+struct Options {
+    input: PathBuf,
+    output: PathBuf,
+    include_paths: Vec<PathBuf>,
+    listing: bool,
+}
+
+impl Options {
+    fn parse(args: Vec<String>) -> Result<Self, String> {
+        let mut input = None;
+        let mut output = None;
+        let mut include_paths = Vec::new();
+        let mut listing = false;
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-o" | "--output" => {
+                    let path = args.next().ok_or("-o requires an output path")?;
+                    output = Some(PathBuf::from(path));
+                }
+                "-I" | "--include" => {
+                    let path = args.next().ok_or("-I requires a directory")?;
+                    include_paths.push(PathBuf::from(path));
+                }
+                "-l" | "--listing" => {
+                    listing = true;
+                }
+                "-h" | "--help" => {
+                    return Err(String::new())
+                }
+                flag if flag.starts_with('-') => {
+                    return Err(format!("unknown option {}", flag))
+                }
+                path => {
+                    if input.is_some() {
+                        return Err(String::from("only one input file can be given"))
+                    }
+                    input = Some(PathBuf::from(path));
+                }
+            }
+        }
+
+        let input = input.ok_or("no input file given")?;
+        let output = output.unwrap_or_else(|| input.with_extension("vdgo"));
+        Ok(Self {
+            input,
+            output,
+            include_paths,
+            listing,
+        })
+    }
+}
+
+fn main() {
+    let opts = match Options::parse(env::args().skip(1).collect()) {
+        Ok(opts) => opts,
+        Err(msg) => {
+            if !msg.is_empty() {
+                eprintln!("vdg-asm: {}", msg);
+            }
+            eprintln!("{}", USAGE);
+            process::exit(EX_USAGE)
+        }
+    };
+
+    let source = match fs::read_to_string(&opts.input) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("vdg-asm: could not read {}: {}", opts.input.display(), e);
+            process::exit(EX_NOINPUT)
+        }
+    };
+
+    let mut assembler = Assembler::new();
+    assembler.allow_undefined(true);
+    for dir in &opts.include_paths {
+        assembler.add_include_path(dir.clone());
+    }
+    let image = match assembler.assemble_file(&opts.input, &source) {
+        Ok(image) => image,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(EX_DATAERR)
+        }
+    };
+
+    if opts.listing {
+        print_listing(&image, &assembler);
+    }
+
+    if let Err(e) = fs::write(&opts.output, assembler.to_object().to_bytes()) {
+        eprintln!("vdg-asm: could not write {}: {}", opts.output.display(), e);
+        process::exit(EX_CANTCREAT)
+    }
+}
+
+/// Prints each address, its encoded bytes and the source line it came from.
+fn print_listing(image: &[u8], assembler: &Assembler) {
+    let sources: Vec<String> = assembler.files()
+        .iter()
+        .map(|file| fs::read_to_string(file).unwrap_or_default())
+        .collect();
+    for loc in assembler.source_map() {
+        let lines: Vec<&str> = sources[loc.context.file].lines().collect();
+        let bytes: Vec<String> = image[loc.address..loc.address + loc.size]
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        let line = lines.get(loc.context.line as usize - 1).unwrap_or(&"");
+        println!("{:04x}  {:<30} {:>4} | {}", loc.address, bytes.join(" "), loc.context.line, line.trim());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_options() {
+        assert_eq!(Options::parse(args(&["prog.vasm"])), Ok(Options {
+            input: PathBuf::from("prog.vasm"),
+            output: PathBuf::from("prog.vdgo"),
+            include_paths: Vec::new(),
+            listing: false,
+        }));
+        assert_eq!(Options::parse(args(&["--listing", "prog.vasm", "-o", "out.bin", "-I", "lib"])), Ok(Options {
+            input: PathBuf::from("prog.vasm"),
+            output: PathBuf::from("out.bin"),
+            include_paths: vec![PathBuf::from("lib")],
+            listing: true,
+        }));
+        assert!(Options::parse(args(&[])).is_err());
+        assert!(Options::parse(args(&["prog.vasm", "-o"])).is_err());
+        assert!(Options::parse(args(&["prog.vasm", "-I"])).is_err());
+        assert!(Options::parse(args(&["prog.vasm", "--bogus"])).is_err());
+        assert!(Options::parse(args(&["a.vasm", "b.vasm"])).is_err());
+    }
+}
@@ -0,0 +1,126 @@
+//! The linker for Oxidizer object files.
+//!
+//! Links several `.vdgo` files produced by `vdg-asm` into one object
+//! that can be run with `verdigris run`.
+//! Exit codes follow the BSD sysexits convention.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+use vdg_oxidizer::linker::Linker;
+use vdg_oxidizer::object::Object;
+
+const USAGE: &str = "Usage: vdg-ld <input.vdgo>... [-o <output>]";
+
+const EX_USAGE: i32 = 64;
+const EX_DATAERR: i32 = 65;
+const EX_NOINPUT: i32 = 66;
+const EX_CANTCREAT: i32 = 73;
+
+#[derive(Debug, Clone, PartialEq)]
+struct Options {
+    inputs: Vec<PathBuf>,
+    output: PathBuf,
+}
+
+impl Options {
+    fn parse(args: Vec<String>) -> Result<Self, String> {
+        let mut inputs = Vec::new();
+        let mut output = None;
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-o" | "--output" => {
+                    let path = args.next().ok_or("-o requires an output path")?;
+                    output = Some(PathBuf::from(path));
+                }
+                "-h" | "--help" => {
+                    return Err(String::new())
+                }
+                flag if flag.starts_with('-') => {
+                    return Err(format!("unknown option {}", flag))
+                }
+                path => {
+                    inputs.push(PathBuf::from(path));
+                }
+            }
+        }
+
+        if inputs.is_empty() {
+            return Err(String::from("no input files given"))
+        }
+        let output = output.unwrap_or_else(|| PathBuf::from("a.vdgo"));
+        Ok(Self {
+            inputs,
+            output,
+        })
+    }
+}
+
+fn main() {
+    let opts = match Options::parse(env::args().skip(1).collect()) {
+        Ok(opts) => opts,
+        Err(msg) => {
+            if !msg.is_empty() {
+                eprintln!("vdg-ld: {}", msg);
+            }
+            eprintln!("{}", USAGE);
+            process::exit(EX_USAGE)
+        }
+    };
+
+    let mut linker = Linker::new();
+    for input in &opts.inputs {
+        let bytes = match fs::read(input) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                eprintln!("vdg-ld: could not read {}: {}", input.display(), e);
+                process::exit(EX_NOINPUT)
+            }
+        };
+        match Object::from_bytes(&bytes) {
+            Ok(object) => linker.add(input.display().to_string(), object),
+            Err(e) => {
+                eprintln!("{}: {}", input.display(), e);
+                process::exit(EX_DATAERR)
+            }
+        }
+    }
+
+    let linked = match linker.link() {
+        Ok(linked) => linked,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(EX_DATAERR)
+        }
+    };
+
+    if let Err(e) = fs::write(&opts.output, linked.to_bytes()) {
+        eprintln!("vdg-ld: could not write {}: {}", opts.output.display(), e);
+        process::exit(EX_CANTCREAT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_options() {
+        assert_eq!(Options::parse(args(&["a.vdgo", "b.vdgo", "-o", "prog.vdgo"])), Ok(Options {
+            inputs: vec![PathBuf::from("a.vdgo"), PathBuf::from("b.vdgo")],
+            output: PathBuf::from("prog.vdgo"),
+        }));
+        assert_eq!(Options::parse(args(&["a.vdgo"])).unwrap().output, PathBuf::from("a.vdgo"));
+        assert!(Options::parse(args(&[])).is_err());
+        assert!(Options::parse(args(&["a.vdgo", "-o"])).is_err());
+        assert!(Options::parse(args(&["a.vdgo", "--bogus"])).is_err());
+    }
+}
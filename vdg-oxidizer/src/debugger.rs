@@ -0,0 +1,839 @@
+//! An interactive debugger for programs running on the Oxidizer VM.
+//!
+//! The debugger drives a VM one instruction at a time with `VM::run_once`,
+//! stopping at breakpoints, when a watched register or word of memory
+//! changes, or when the program halts. It is used by the REPL's `.debug`
+//! command and by `verdigris debug`.
+//!
+//! While it is attached, the debugger keeps the VM's journal turned on, so
+//! the most recent instructions can be undone to run the program backwards.
+//!
+//! Locations can be given as addresses (`0x1c`) or as labels (`@loop`),
+//! and the commands are:
+//!
+//! ```text
+//! break <loc>        b   stop when execution reaches loc
+//! delete <loc>       d   remove a breakpoint
+//! watch <$r|[loc]>   w   stop when a register or word of memory changes
+//! unwatch <$r|[loc]>     remove a watchpoint
+//! step               s   run one instruction
+//! next               n   run one instruction, stepping over calls
+//! continue           c   run until something stops the program
+//! reverse-step       rs  undo the last instruction
+//! reverse-continue   rc  run backwards until something stops the program
+//! last <$r|[loc]>        find the last instruction to write a value
+//! history [n]            show or set how many instructions are kept
+//! backtrace          bt  show the return address of every call frame
+//! list               l   disassemble around the program counter
+//! registers          r   show the registers
+//! info               i   list breakpoints and watchpoints
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+
+use linefeed::{Interface, Terminal, reader::ReadResult};
+
+use crate::assembler::lexer::parse_number;
+use crate::disassembler::{Disassembler, Line};
+use crate::vm::{Instruction, Opcode, VM};
+use crate::vm::journal::Journal;
+use crate::vm::vm::VMError;
+
+const PROMPT: &str = "(dbg) ";
+
+/// How many instructions `list` shows either side of the program counter.
+const LIST_CONTEXT: usize = 3;
+
+/// How many instructions can be undone, unless changed with `history`.
+pub const DEFAULT_HISTORY: usize = 10_000;
+
+/// How many instructions a single command runs before handing control
+/// back, so that an infinite loop cannot hang the debugger.
+pub const RUN_LIMIT: usize = 10_000_000;
+
+pub struct Debugger<'a> {
+    vm: &'a mut VM,
+    labels: HashMap<String, usize>,
+    breakpoints: Vec<usize>,
+    watchpoints: Vec<Watch>,
+    /// Set once the program has run `hlt`, after which it cannot go on.
+    halted: bool,
+    /// Whether the debugger turned on the VM's journal, and so should turn
+    /// it off again when it is done.
+    owns_journal: bool,
+    run_limit: usize,
+}
+
+impl<'a> Debugger<'a> {
+    pub fn new(vm: &'a mut VM) -> Self {
+        let owns_journal = vm.journal().is_none();
+        if owns_journal {
+            vm.enable_journal(DEFAULT_HISTORY);
+        }
+        Self {
+            vm,
+            labels: HashMap::new(),
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            halted: false,
+            owns_journal,
+            run_limit: RUN_LIMIT,
+        }
+    }
+
+    /// Changes how many instructions a single command can run.
+    pub fn set_run_limit(&mut self, limit: usize) {
+        self.run_limit = limit;
+    }
+
+    /// Names an address, so it can be used as a location and is shown
+    /// in listings and backtraces.
+    pub fn add_label(&mut self, name: &str, addr: usize) {
+        self.labels.insert(name.to_string(), addr);
+    }
+
+    /// Reads commands from `interface` until `quit` or the end of input.
+    /// The caller should set its own prompt again afterwards.
+    pub fn interact<T: Terminal>(&mut self, interface: &Interface<T>) -> Result<(), DebugError> {
+        interface.set_prompt(PROMPT)?;
+        println!("{}", self.current_line());
+        loop {
+            let line = match interface.read_line()? {
+                ReadResult::Input(line) => line,
+                ReadResult::Eof => break,
+                ReadResult::Signal(_) => continue,
+            };
+            interface.add_history(line.clone());
+            match DebugCmd::parse(&line) {
+                Ok(Some(DebugCmd::Quit)) => break,
+                Ok(Some(cmd)) => match self.exec(cmd) {
+                    Ok(output) => println!("{}", output),
+                    Err(e) => eprintln!("{}", e),
+                },
+                Ok(None) => {}
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs a command and returns what it has to say.
+    pub fn exec(&mut self, cmd: DebugCmd) -> Result<String, DebugError> {
+        match cmd {
+            DebugCmd::Break(loc) => {
+                let addr = self.resolve(&loc)?;
+                if !self.breakpoints.contains(&addr) {
+                    self.breakpoints.push(addr);
+                }
+                Ok(format!("Breakpoint at {}", self.describe(addr)))
+            }
+            DebugCmd::Delete(loc) => {
+                let addr = self.resolve(&loc)?;
+                let count = self.breakpoints.len();
+                self.breakpoints.retain(|&bp| bp != addr);
+                if self.breakpoints.len() == count {
+                    return Err(DebugError::NoSuchBreakpoint(loc))
+                }
+                Ok(format!("Deleted breakpoint at {}", self.describe(addr)))
+            }
+            DebugCmd::Watch(target) => {
+                let watch = self.parse_watch(&target)?;
+                self.value_of(watch).map_err(|_| DebugError::InvalidLocation(target))?;
+                if !self.watchpoints.contains(&watch) {
+                    self.watchpoints.push(watch);
+                }
+                Ok(format!("Watching {}", watch))
+            }
+            DebugCmd::Unwatch(target) => {
+                let watch = self.parse_watch(&target)?;
+                let count = self.watchpoints.len();
+                self.watchpoints.retain(|&w| w != watch);
+                if self.watchpoints.len() == count {
+                    return Err(DebugError::NoSuchWatchpoint(target))
+                }
+                Ok(format!("Stopped watching {}", watch))
+            }
+            DebugCmd::Step => self.step().map(|stop| self.report(stop)),
+            DebugCmd::Next => self.step_over().map(|stop| self.report(stop)),
+            DebugCmd::Continue => self.cont().map(|stop| self.report(stop)),
+            DebugCmd::ReverseStep => {
+                let stop = self.reverse_step()?;
+                Ok(self.report(stop))
+            }
+            DebugCmd::ReverseContinue => {
+                let stop = self.reverse_cont()?;
+                Ok(self.report(stop))
+            }
+            DebugCmd::Last(target) => {
+                let watch = self.parse_watch(&target)?;
+                let journal = self.journal()?;
+                let write = match watch {
+                    Watch::Register(reg) => journal.last_register_write(reg),
+                    Watch::Memory(addr) => journal.last_memory_write(addr as i64),
+                };
+                match write {
+                    Some((entry, 0)) => {
+                        Ok(format!("{} was written by the last instruction, at {}", watch, self.describe(entry.pc())))
+                    }
+                    Some((entry, ago)) => {
+                        Ok(format!("{} was written at {}, {} instructions ago", watch, self.describe(entry.pc()), ago + 1))
+                    }
+                    None => {
+                        Ok(format!("{} has not been written in the last {} instructions", watch, journal.len()))
+                    }
+                }
+            }
+            DebugCmd::History(limit) => {
+                if let Some(limit) = limit {
+                    self.vm.journal_mut().ok_or(DebugError::NoHistory)?.set_limit(limit);
+                }
+                let journal = self.journal()?;
+                Ok(format!("{} of the last {} instructions can be undone", journal.len(), journal.limit()))
+            }
+            DebugCmd::Backtrace => {
+                let frames: Vec<String> = self.backtrace()
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &addr)| format!("#{} {}", i, self.describe(addr)))
+                    .collect();
+                Ok(frames.join("\n"))
+            }
+            DebugCmd::List => {
+                let lines: Vec<String> = self.listing()
+                    .iter()
+                    .map(|line| self.format_line(line))
+                    .collect();
+                Ok(lines.join("\n"))
+            }
+            DebugCmd::Registers => {
+                let regs: Vec<String> = self.vm.registers()
+                    .chunks(8)
+                    .enumerate()
+                    .map(|(row, regs)| {
+                        let regs: Vec<String> = regs.iter()
+                            .enumerate()
+                            .map(|(i, value)| format!("${:<2} {:>11}", row * 8 + i, value))
+                            .collect();
+                        regs.join("  ")
+                    })
+                    .collect();
+                Ok(format!("{}\neq: {}  rem: {}", regs.join("\n"), self.vm.eq_flag(), self.vm.remainder()))
+            }
+            DebugCmd::Info => {
+                let mut info = Vec::new();
+                for &addr in &self.breakpoints {
+                    info.push(format!("break {}", self.describe(addr)));
+                }
+                for &watch in &self.watchpoints {
+                    info.push(format!("watch {}", watch));
+                }
+                if info.is_empty() {
+                    info.push(String::from("No breakpoints or watchpoints"));
+                }
+                let journal = self.journal()?;
+                info.push(format!("history {} of {}", journal.len(), journal.limit()));
+                Ok(info.join("\n"))
+            }
+            DebugCmd::Help => {
+                Ok(HELP.to_string())
+            }
+            DebugCmd::Quit => Ok(String::new()),
+        }
+    }
+
+    /// Runs a single instruction.
+    pub fn step(&mut self) -> Result<Stop, DebugError> {
+        Ok(self.run_one()?.unwrap_or(Stop::Stepped))
+    }
+
+    /// Runs a single instruction, running the whole of any function it calls.
+    pub fn step_over(&mut self) -> Result<Stop, DebugError> {
+        let pc = self.vm.pc();
+        match Instruction::decode(&self.vm.program()[pc.min(self.vm.program().len())..]) {
+            Ok((inst, len)) if inst.inst == Opcode::Call => {
+                let depth = self.vm.memory().return_addresses().len();
+                self.run_until(|vm| vm.pc() == pc + len && vm.memory().return_addresses().len() <= depth)
+            }
+            _ => self.step(),
+        }
+    }
+
+    /// Runs until a breakpoint, a watchpoint, or the end of the program.
+    pub fn cont(&mut self) -> Result<Stop, DebugError> {
+        self.run_until(|_| false)
+    }
+
+    /// Undoes the last instruction that ran.
+    pub fn reverse_step(&mut self) -> Result<Stop, DebugError> {
+        if !self.vm.step_back()? {
+            return Ok(Stop::HistoryStart)
+        }
+        self.halted = false;
+        Ok(Stop::Stepped)
+    }
+
+    /// Undoes instructions until one that starts at a breakpoint or
+    /// changed something watched, or until there are none left to undo.
+    pub fn reverse_cont(&mut self) -> Result<Stop, DebugError> {
+        loop {
+            let after: Vec<i32> = self.watchpoints.iter()
+                .map(|&watch| self.value_of(watch).unwrap_or(0))
+                .collect();
+            if let Stop::HistoryStart = self.reverse_step()? {
+                return Ok(Stop::HistoryStart)
+            }
+            for (&watch, new) in self.watchpoints.iter().zip(after) {
+                let old = self.value_of(watch).unwrap_or(0);
+                if new != old {
+                    return Ok(Stop::Watchpoint(watch, old, new))
+                }
+            }
+            if self.breakpoints.contains(&self.vm.pc()) {
+                return Ok(Stop::Breakpoint(self.vm.pc()))
+            }
+        }
+    }
+
+    /// Returns the address of the next instruction in every call frame,
+    /// starting with the program counter.
+    pub fn backtrace(&self) -> Vec<usize> {
+        let mut frames = vec![self.vm.pc()];
+        frames.extend(self.vm.memory().return_addresses());
+        frames
+    }
+
+    /// Disassembles the instructions around the program counter.
+    pub fn listing(&self) -> Vec<Line> {
+        let lines = self.disassembler().disassemble();
+        let pc = self.vm.pc();
+        let current = lines.iter()
+            .position(|line| line.address + line.bytes.len() > pc)
+            .unwrap_or(lines.len());
+        let start = current.saturating_sub(LIST_CONTEXT);
+        let end = (current + LIST_CONTEXT + 1).min(lines.len());
+        lines[start..end].to_vec()
+    }
+
+    /// Runs instructions until `done` is true after one of them, or
+    /// something else stops the program. The instruction at the program
+    /// counter always runs, even if there is a breakpoint on it.
+    fn run_until<F: Fn(&VM) -> bool>(&mut self, done: F) -> Result<Stop, DebugError> {
+        for _ in 0..self.run_limit {
+            if let Some(stop) = self.run_one()? {
+                return Ok(stop)
+            }
+            if self.breakpoints.contains(&self.vm.pc()) {
+                return Ok(Stop::Breakpoint(self.vm.pc()))
+            }
+            if done(self.vm) {
+                return Ok(Stop::Stepped)
+            }
+        }
+        Ok(Stop::Limit(self.run_limit))
+    }
+
+    /// Runs the instruction at the program counter, returning why the
+    /// program stopped if it was halted or changed something watched.
+    fn run_one(&mut self) -> Result<Option<Stop>, DebugError> {
+        if self.halted {
+            return Ok(Some(Stop::Halted))
+        }
+        if self.vm.pc() >= self.vm.program().len() {
+            return Ok(Some(Stop::Finished))
+        }
+        let before: Vec<i32> = self.watchpoints.iter()
+            .map(|&watch| self.value_of(watch).unwrap_or(0))
+            .collect();
+        if self.vm.run_once()? {
+            self.halted = true;
+            return Ok(Some(Stop::Halted))
+        }
+        for (&watch, old) in self.watchpoints.iter().zip(before) {
+            let new = self.value_of(watch).unwrap_or(0);
+            if new != old {
+                return Ok(Some(Stop::Watchpoint(watch, old, new)))
+            }
+        }
+        Ok(None)
+    }
+
+    fn journal(&self) -> Result<&Journal, DebugError> {
+        self.vm.journal().ok_or(DebugError::NoHistory)
+    }
+
+    fn value_of(&self, watch: Watch) -> Result<i32, VMError> {
+        match watch {
+            Watch::Register(reg) => Ok(self.vm.registers()[reg]),
+            Watch::Memory(addr) => self.vm.load(addr as i64),
+        }
+    }
+
+    /// Turns a location, either a label or an address, into an address.
+    fn resolve(&self, loc: &str) -> Result<usize, DebugError> {
+        let name = loc.strip_prefix('@').unwrap_or(loc);
+        if let Some(&addr) = self.labels.get(name) {
+            return Ok(addr)
+        }
+        match parse_number(loc) {
+            Some(addr) if addr >= 0 => Ok(addr as usize),
+            _ if loc.starts_with('@') => Err(DebugError::UnknownLabel(name.to_string())),
+            _ => Err(DebugError::InvalidLocation(loc.to_string())),
+        }
+    }
+
+    fn parse_watch(&self, target: &str) -> Result<Watch, DebugError> {
+        if let Some(reg) = target.strip_prefix('$') {
+            return match reg.parse::<usize>() {
+                Ok(reg) if reg < 32 => Ok(Watch::Register(reg)),
+                _ => Err(DebugError::InvalidLocation(target.to_string())),
+            }
+        }
+        match target.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+            Some(loc) => Ok(Watch::Memory(self.resolve(loc.trim())?)),
+            None => Err(DebugError::InvalidLocation(target.to_string())),
+        }
+    }
+
+    fn report(&self, stop: Stop) -> String {
+        match stop {
+            Stop::Stepped => self.current_line(),
+            Stop::Breakpoint(addr) => format!("Breakpoint at {}\n{}", self.describe(addr), self.current_line()),
+            Stop::Watchpoint(watch, old, new) => {
+                format!("{} changed from {} to {}\n{}", watch, old, new, self.current_line())
+            }
+            Stop::Halted => String::from("Program halted"),
+            Stop::Finished => String::from("Reached the end of the program"),
+            Stop::HistoryStart => format!("No more history to undo\n{}", self.current_line()),
+            Stop::Limit(count) => {
+                format!("Stopped after {} instructions, continue to keep going\n{}", count, self.current_line())
+            }
+        }
+    }
+
+    /// Disassembles the instruction at the program counter.
+    fn current_line(&self) -> String {
+        self.listing()
+            .iter()
+            .find(|line| line.address == self.vm.pc())
+            .map(|line| self.format_line(line))
+            .unwrap_or_else(|| format!("=> {:04x}", self.vm.pc()))
+    }
+
+    fn format_line(&self, line: &Line) -> String {
+        let marker = if line.address == self.vm.pc() { "=>" } else { "  " };
+        let mut text = String::new();
+        if let Some(label) = &line.label {
+            text.push_str(&format!("   {}:\n", label));
+        }
+        text.push_str(&format!("{} {:04x}:    {}", marker, line.address, line.text));
+        text
+    }
+
+    /// Shows an address along with the label it falls under, if any.
+    fn describe(&self, addr: usize) -> String {
+        let label = self.labels.iter()
+            .filter(|(_, &start)| start <= addr)
+            .max_by_key(|(_, &start)| start);
+        match label {
+            Some((name, &start)) if start == addr => format!("{:#06x} <{}>", addr, name),
+            Some((name, &start)) => format!("{:#06x} <{}+{}>", addr, name, addr - start),
+            None => format!("{:#06x}", addr),
+        }
+    }
+
+    fn disassembler(&self) -> Disassembler<'_> {
+        let mut disasm = Disassembler::new(self.vm.program());
+        for (name, &addr) in &self.labels {
+            disasm.add_label(addr, name);
+        }
+        disasm
+    }
+}
+
+/// Why the debugger handed control back.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stop {
+    /// The requested instructions ran.
+    Stepped,
+    Breakpoint(usize),
+    /// A watched value changed from the first value to the second.
+    Watchpoint(Watch, i32, i32),
+    Halted,
+    /// The program counter has run off the end of the program.
+    Finished,
+    /// Running backwards undid every instruction in the journal.
+    HistoryStart,
+    /// The given number of instructions ran without anything else
+    /// stopping the program.
+    Limit(usize),
+}
+
+impl Drop for Debugger<'_> {
+    fn drop(&mut self) {
+        if self.owns_journal {
+            self.vm.disable_journal();
+        }
+    }
+}
+
+/// A register or word of memory to stop on when it changes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Watch {
+    Register(usize),
+    Memory(usize),
+}
+
+impl fmt::Display for Watch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Register(reg) => write!(f, "${}", reg),
+            Self::Memory(addr) => write!(f, "[{:#06x}]", addr),
+        }
+    }
+}
+
+const HELP: &str = "\
+break <loc>        b   Stop when execution reaches loc, an address or @label
+delete <loc>       d   Remove a breakpoint
+watch <$r|[loc]>   w   Stop when a register or word of memory changes
+unwatch <$r|[loc]>     Remove a watchpoint
+step               s   Run one instruction
+next               n   Run one instruction, stepping over calls
+continue           c   Run until a breakpoint, watchpoint or halt
+reverse-step       rs  Undo the last instruction
+reverse-continue   rc  Run backwards to a breakpoint or watchpoint
+last <$r|[loc]>        Find the last instruction to write a value
+history [n]            Show or set how many instructions can be undone
+backtrace          bt  Show the return address of every call frame
+list               l   Disassemble around the program counter
+registers          r   Show the registers
+info               i   List breakpoints and watchpoints
+quit               q   Leave the debugger";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DebugCmd {
+    Break(String),
+    Delete(String),
+    Watch(String),
+    Unwatch(String),
+    Step,
+    Next,
+    Continue,
+    ReverseStep,
+    ReverseContinue,
+    Last(String),
+    History(Option<usize>),
+    Backtrace,
+    List,
+    Registers,
+    Info,
+    Help,
+    Quit,
+}
+
+impl DebugCmd {
+    /// Parses a line typed into the debugger. Empty lines are `None`.
+    pub fn parse(line: &str) -> Result<Option<Self>, DebugError> {
+        let line = line.trim();
+        let (name, arg) = match line.find(char::is_whitespace) {
+            Some(split) => (&line[..split], line[split..].trim()),
+            None => (line, ""),
+        };
+        let with_arg = |cmd: fn(String) -> Self, usage: &'static str| {
+            if arg.is_empty() {
+                Err(DebugError::Usage(usage))
+            } else {
+                Ok(Some(cmd(arg.to_string())))
+            }
+        };
+        let cmd = match name {
+            "" => return Ok(None),
+            "break" | "b" => return with_arg(Self::Break, "break <address|@label>"),
+            "delete" | "d" => return with_arg(Self::Delete, "delete <address|@label>"),
+            "watch" | "w" => return with_arg(Self::Watch, "watch <$register|[address]>"),
+            "unwatch" => return with_arg(Self::Unwatch, "unwatch <$register|[address]>"),
+            "step" | "s" => Self::Step,
+            "next" | "n" => Self::Next,
+            "continue" | "c" => Self::Continue,
+            "reverse-step" | "rs" => Self::ReverseStep,
+            "reverse-continue" | "rc" => Self::ReverseContinue,
+            "last" => return with_arg(Self::Last, "last <$register|[address]>"),
+            "history" if arg.is_empty() => Self::History(None),
+            "history" => {
+                return match arg.parse() {
+                    Ok(limit) => Ok(Some(Self::History(Some(limit)))),
+                    Err(_) => Err(DebugError::Usage("history [instructions]")),
+                }
+            }
+            "backtrace" | "bt" => Self::Backtrace,
+            "list" | "l" => Self::List,
+            "registers" | "r" => Self::Registers,
+            "info" | "i" => Self::Info,
+            "help" | "h" => Self::Help,
+            "quit" | "q" => Self::Quit,
+            _ => return Err(DebugError::UnknownCommand(name.to_string())),
+        };
+        if !arg.is_empty() {
+            return Err(DebugError::UnknownCommand(line.to_string()))
+        }
+        Ok(Some(cmd))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DebugError {
+    ReadError,
+    UnknownCommand(String),
+    Usage(&'static str),
+    UnknownLabel(String),
+    InvalidLocation(String),
+    NoSuchBreakpoint(String),
+    NoSuchWatchpoint(String),
+    NoHistory,
+    Vm(VMError),
+}
+
+impl std::error::Error for DebugError {}
+
+impl fmt::Display for DebugError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::ReadError => {
+                write!(f, "Error: unable to read line")
+            }
+            Self::UnknownCommand(cmd) => {
+                write!(f, "Error: unknown command {}, type help for a list of commands", cmd)
+            }
+            Self::Usage(usage) => {
+                write!(f, "Usage: {}", usage)
+            }
+            Self::UnknownLabel(name) => {
+                write!(f, "Error: no label called @{}", name)
+            }
+            Self::InvalidLocation(loc) => {
+                write!(f, "Error: {} is not a valid location", loc)
+            }
+            Self::NoSuchBreakpoint(loc) => {
+                write!(f, "Error: there is no breakpoint at {}", loc)
+            }
+            Self::NoSuchWatchpoint(target) => {
+                write!(f, "Error: {} is not being watched", target)
+            }
+            Self::NoHistory => {
+                write!(f, "Error: the VM is not keeping a history of instructions")
+            }
+            Self::Vm(err) => {
+                write!(f, "{}", err)
+            }
+        }
+    }
+}
+
+impl From<std::io::Error> for DebugError {
+    fn from(_from: std::io::Error) -> Self {
+        Self::ReadError
+    }
+}
+
+impl From<VMError> for DebugError {
+    fn from(from: VMError) -> Self {
+        Self::Vm(from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+
+    const FACT: &str = "\
+        mov $1 4\n\
+        call @fact\n\
+        hlt\n\
+        fact: { le $1 1\n\
+        jeq @base\n\
+        push $1\n\
+        dec $1 $1\n\
+        call @fact\n\
+        pop $1\n\
+        mul $0 $1 $0\n\
+        ret }\n\
+        base: { mov $0 1\n\
+        ret }";
+
+    fn debug_fact<F: FnOnce(&mut Debugger)>(test: F) {
+        let mut assembler = Assembler::new();
+        let mut vm = VM::new(assembler.assemble_source(FACT).unwrap());
+        let mut debugger = Debugger::new(&mut vm);
+        for (name, sym) in assembler.symbols() {
+            debugger.add_label(name, sym.offset());
+        }
+        test(&mut debugger);
+    }
+
+    #[test]
+    fn test_breakpoints_and_backtrace() {
+        debug_fact(|debugger| {
+            let base = debugger.resolve("@base").unwrap();
+            debugger.exec(DebugCmd::Break(String::from("@base"))).unwrap();
+            assert_eq!(debugger.cont().unwrap(), Stop::Breakpoint(base));
+            // called from main, then three times from inside fact
+            let frames = debugger.backtrace();
+            assert_eq!(frames.len(), 5);
+            assert_eq!(frames[0], base);
+            assert_eq!(debugger.describe(frames[4]), format!("{:#06x}", frames[4]));
+            assert!(debugger.describe(frames[1]).contains("<fact+"));
+
+            debugger.exec(DebugCmd::Delete(base.to_string())).unwrap();
+            assert_eq!(debugger.cont().unwrap(), Stop::Halted);
+            assert_eq!(debugger.vm.registers()[0], 24);
+            assert_eq!(debugger.step().unwrap(), Stop::Halted);
+        });
+    }
+
+    #[test]
+    fn test_step_and_next() {
+        debug_fact(|debugger| {
+            assert_eq!(debugger.step().unwrap(), Stop::Stepped);
+            // stepping over the call runs all of fact
+            assert_eq!(debugger.step_over().unwrap(), Stop::Stepped);
+            assert_eq!(debugger.vm.registers()[0], 24);
+            assert_eq!(debugger.backtrace().len(), 1);
+            assert_eq!(debugger.step().unwrap(), Stop::Halted);
+        });
+
+        debug_fact(|debugger| {
+            debugger.step().unwrap();
+            // a breakpoint inside the function still stops next
+            debugger.exec(DebugCmd::Break(String::from("@base"))).unwrap();
+            let base = debugger.resolve("base").unwrap();
+            assert_eq!(debugger.step_over().unwrap(), Stop::Breakpoint(base));
+            debugger.step().unwrap();
+            debugger.step().unwrap();
+            assert_eq!(debugger.describe(debugger.vm.pc()), format!("{:#06x} <fact+27>", debugger.vm.pc()));
+        });
+    }
+
+    #[test]
+    fn test_run_limit() {
+        let mut vm = VM::new(Assembler::new().assemble_source("mov $1 0\nspin: { inc $1 $1\njmp @spin }").unwrap());
+        let mut debugger = Debugger::new(&mut vm);
+        debugger.set_run_limit(100);
+        assert_eq!(debugger.cont().unwrap(), Stop::Limit(100));
+        assert_eq!(debugger.cont().unwrap(), Stop::Limit(100));
+        assert_eq!(debugger.vm.registers()[1], 100);
+        assert!(debugger.exec(DebugCmd::Continue).unwrap().starts_with("Stopped after 100 instructions"));
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        debug_fact(|debugger| {
+            debugger.exec(DebugCmd::Watch(String::from("$0"))).unwrap();
+            assert_eq!(debugger.cont().unwrap(), Stop::Watchpoint(Watch::Register(0), 0, 1));
+            assert_eq!(debugger.cont().unwrap(), Stop::Watchpoint(Watch::Register(0), 1, 2));
+            debugger.exec(DebugCmd::Unwatch(String::from("$0"))).unwrap();
+            assert_eq!(debugger.cont().unwrap(), Stop::Halted);
+        });
+
+        let mut assembler = Assembler::new();
+        let mut vm = VM::new(assembler.assemble_source("mov $1 7\nmov [@cell] $1\n.data\ncell: { .word 0 }").unwrap());
+        let mut debugger = Debugger::new(&mut vm);
+        let cell = assembler.address_of("cell").unwrap();
+        debugger.add_label("cell", cell);
+        debugger.exec(DebugCmd::Watch(String::from("[@cell]"))).unwrap();
+        assert_eq!(debugger.cont().unwrap(), Stop::Watchpoint(Watch::Memory(cell), 0, 7));
+    }
+
+    #[test]
+    fn test_reverse_execution() {
+        debug_fact(|debugger| {
+            let start = debugger.vm.clone();
+            let fact = debugger.resolve("@fact").unwrap();
+            assert_eq!(debugger.cont().unwrap(), Stop::Halted);
+            assert_eq!(debugger.vm.registers()[0], 24);
+
+            // back to the start of the innermost call, then run forwards again
+            debugger.exec(DebugCmd::Break(String::from("@fact"))).unwrap();
+            assert_eq!(debugger.reverse_cont().unwrap(), Stop::Breakpoint(fact));
+            assert_eq!(debugger.backtrace().len(), 5);
+            assert_eq!(debugger.vm.registers()[1], 1);
+            debugger.exec(DebugCmd::Delete(String::from("@fact"))).unwrap();
+            assert_eq!(debugger.cont().unwrap(), Stop::Halted);
+
+            debugger.exec(DebugCmd::Watch(String::from("$0"))).unwrap();
+            assert_eq!(debugger.reverse_cont().unwrap(), Stop::Watchpoint(Watch::Register(0), 6, 24));
+            assert!(debugger.exec(DebugCmd::List).unwrap().contains("=> "));
+            debugger.exec(DebugCmd::Unwatch(String::from("$0"))).unwrap();
+            assert_eq!(debugger.reverse_cont().unwrap(), Stop::HistoryStart);
+            assert_eq!(debugger.reverse_step().unwrap(), Stop::HistoryStart);
+            assert_eq!(debugger.vm.registers(), start.registers());
+            assert_eq!(debugger.vm.pc(), 0);
+        });
+    }
+
+    #[test]
+    fn test_last_write_and_history() {
+        debug_fact(|debugger| {
+            debugger.cont().unwrap();
+            let last = debugger.exec(DebugCmd::Last(String::from("$0"))).unwrap();
+            assert!(last.contains("$0 was written at") && last.contains("<fact+"), "{}", last);
+            assert!(debugger.exec(DebugCmd::Last(String::from("$7"))).unwrap().contains("has not been written"));
+
+            debugger.exec(DebugCmd::History(Some(2))).unwrap();
+            assert!(debugger.exec(DebugCmd::Info).unwrap().contains("history 2 of 2"));
+            debugger.reverse_step().unwrap();
+            debugger.reverse_step().unwrap();
+            assert_eq!(debugger.reverse_step().unwrap(), Stop::HistoryStart);
+            // only the final `ret` and `hlt` were kept
+            assert_eq!(debugger.step().unwrap(), Stop::Stepped);
+            assert_eq!(debugger.step().unwrap(), Stop::Halted);
+        });
+
+        let mut vm = VM::new(Assembler::new().assemble_source("hlt").unwrap());
+        let mut debugger = Debugger::new(&mut vm);
+        debugger.vm.disable_journal();
+        assert_eq!(debugger.exec(DebugCmd::History(Some(4))), Err(DebugError::NoHistory));
+        assert_eq!(debugger.exec(DebugCmd::Last(String::from("$0"))), Err(DebugError::NoHistory));
+        assert_eq!(debugger.reverse_step().unwrap(), Stop::HistoryStart);
+        debugger.step().unwrap();
+        drop(debugger);
+        assert!(vm.journal().is_none());
+    }
+
+    #[test]
+    fn test_listing() {
+        debug_fact(|debugger| {
+            debugger.exec(DebugCmd::Break(String::from("@fact"))).unwrap();
+            debugger.cont().unwrap();
+            let listing = debugger.exec(DebugCmd::List).unwrap();
+            assert!(listing.contains("   fact:\n=> "));
+            assert!(listing.contains("le $1 1"));
+            assert_eq!(debugger.listing().len(), 2 * LIST_CONTEXT + 1);
+        });
+    }
+
+    #[test]
+    fn test_debug_commands() {
+        assert_eq!(DebugCmd::parse("  ").unwrap(), None);
+        assert_eq!(DebugCmd::parse("b @loop").unwrap(), Some(DebugCmd::Break(String::from("@loop"))));
+        assert_eq!(DebugCmd::parse("watch [ 0x10 ]").unwrap(), Some(DebugCmd::Watch(String::from("[ 0x10 ]"))));
+        assert_eq!(DebugCmd::parse("bt").unwrap(), Some(DebugCmd::Backtrace));
+        assert_eq!(DebugCmd::parse("rc").unwrap(), Some(DebugCmd::ReverseContinue));
+        assert_eq!(DebugCmd::parse("history 50").unwrap(), Some(DebugCmd::History(Some(50))));
+        assert_eq!(DebugCmd::parse("history lots"), Err(DebugError::Usage("history [instructions]")));
+        assert_eq!(DebugCmd::parse("break"), Err(DebugError::Usage("break <address|@label>")));
+        assert!(matches!(DebugCmd::parse("jump 4"), Err(DebugError::UnknownCommand(_))));
+        assert!(matches!(DebugCmd::parse("step 4"), Err(DebugError::UnknownCommand(_))));
+
+        debug_fact(|debugger| {
+            assert_eq!(debugger.exec(DebugCmd::Break(String::from("@nowhere"))), Err(DebugError::UnknownLabel(String::from("nowhere"))));
+            assert!(matches!(debugger.exec(DebugCmd::Watch(String::from("$32"))), Err(DebugError::InvalidLocation(_))));
+            assert!(matches!(debugger.exec(DebugCmd::Watch(String::from("[0x7fffffff]"))), Err(DebugError::InvalidLocation(_))));
+            assert!(matches!(debugger.exec(DebugCmd::Delete(String::from("4"))), Err(DebugError::NoSuchBreakpoint(_))));
+            debugger.exec(DebugCmd::Break(String::from("0x8"))).unwrap();
+            debugger.exec(DebugCmd::Watch(String::from("$3"))).unwrap();
+            assert!(debugger.exec(DebugCmd::Info).unwrap().contains("break 0x0008\nwatch $3"));
+        });
+    }
+}
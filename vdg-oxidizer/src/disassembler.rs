@@ -0,0 +1,181 @@
+//! Turns Oxidizer bytecode back into readable assembly.
+//!
+//! Instructions are decoded with `Instruction::decode`, so the output
+//! always follows the same encoding rules as the VM. When a symbol table
+//! is available, labels are printed above the addresses they name and
+//! jump targets are shown as `@label` uses.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::assembler::Operand;
+use crate::object::Object;
+use crate::vm::{Instruction, Opcode};
+
+#[derive(Debug, Clone)]
+pub struct Disassembler<'a> {
+    program: &'a [u8],
+    labels: HashMap<usize, String>,
+    code_size: usize,
+}
+
+impl<'a> Disassembler<'a> {
+    /// Creates a disassembler for a program with no symbols,
+    /// where every byte is treated as code.
+    pub fn new(program: &'a [u8]) -> Self {
+        Self {
+            program,
+            labels: HashMap::new(),
+            code_size: program.len(),
+        }
+    }
+
+    /// Creates a disassembler for a loaded object image,
+    /// recovering label names from its symbol table.
+    pub fn from_object(object: &Object, image: &'a [u8]) -> Self {
+        let mut disasm = Self::new(image);
+        disasm.code_size = object.code().len();
+        // the exported name wins where two labels share an address
+        for (name, addr) in object.labels().into_iter().rev() {
+            disasm.add_label(addr, &name);
+        }
+        disasm
+    }
+
+    /// Names an address in the program.
+    pub fn add_label(&mut self, addr: usize, name: &str) {
+        self.labels.insert(addr, name.to_string());
+    }
+
+    /// Decodes the whole program into lines of assembly.
+    pub fn disassemble(&self) -> Vec<Line> {
+        let mut lines = Vec::new();
+        let mut addr = 0;
+        while addr < self.program.len() {
+            let line = if addr < self.code_size {
+                self.code_line(addr)
+            } else {
+                self.data_line(addr)
+            };
+            addr += line.bytes.len();
+            lines.push(line);
+        }
+        lines
+    }
+
+    fn code_line(&self, addr: usize) -> Line {
+        let end = self.next_label(addr).min(self.code_size);
+        match Instruction::decode(&self.program[addr..end]) {
+            Ok((mut inst, len)) => {
+                if let Opcode::Jmp | Opcode::Jeq | Opcode::Jne | Opcode::Call = inst.inst {
+                    if let Some(Operand::NumLiteral(target)) = inst.op1 {
+                        if let Some(name) = self.labels.get(&(target as usize)) {
+                            inst.op1 = Some(Operand::LabelUse(name.clone()));
+                        }
+                    }
+                }
+                self.line(addr, len, inst.to_string())
+            }
+            Err(_) => {
+                let byte = self.program[addr];
+                self.line(addr, 1, format!(".byte {:#04x}", byte))
+            }
+        }
+    }
+
+    fn data_line(&self, addr: usize) -> Line {
+        let end = self.next_label(addr);
+        let bytes = &self.program[addr..end];
+        if let Some(nul) = bytes.iter().position(|&b| b == 0) {
+            let text = &bytes[..nul];
+            if !text.is_empty() && text.iter().all(|b| b.is_ascii_graphic() || *b == b' ') {
+                let text = String::from_utf8_lossy(text);
+                return self.line(addr, nul + 1, format!(".string {:?}", text))
+            }
+        }
+        self.line(addr, 1, format!(".byte {:#04x}", bytes[0]))
+    }
+
+    fn line(&self, addr: usize, len: usize, text: String) -> Line {
+        Line {
+            address: addr,
+            label: self.labels.get(&addr).cloned(),
+            bytes: self.program[addr..addr + len].to_vec(),
+            text,
+        }
+    }
+
+    /// Returns the address of the next label after `addr`,
+    /// so that decoding never runs across a label boundary.
+    fn next_label(&self, addr: usize) -> usize {
+        self.labels.keys()
+            .filter(|&&label| label > addr)
+            .min()
+            .copied()
+            .unwrap_or(self.program.len())
+    }
+}
+
+/// A single disassembled instruction or piece of data.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    pub address: usize,
+    pub label: Option<String>,
+    pub bytes: Vec<u8>,
+    pub text: String,
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(label) = &self.label {
+            writeln!(f, "{}:", label)?;
+        }
+        write!(f, "{:04x}:    {}", self.address, self.text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+
+    #[test]
+    fn test_disassemble_raw() {
+        let mut assembler = Assembler::new();
+        let image = assembler.assemble_source("mov $2 500\nadd $2 1 $3\njmp [16]\nhlt").unwrap();
+
+        let text: Vec<String> = Disassembler::new(&image)
+            .disassemble()
+            .iter()
+            .map(|line| line.to_string())
+            .collect();
+        assert_eq!(text, vec![
+            "0000:    mov $2 500",
+            "0008:    add $2 1 $3",
+            "0011:    jmp [16]",
+            "001b:    hlt",
+        ]);
+    }
+
+    #[test]
+    fn test_disassemble_with_symbols() {
+        let mut assembler = Assembler::new();
+        let image = assembler.assemble_source(
+            ".global @main\nmain: { jmp @end }\nend: { hlt }\n.data\nmsg: { .string \"hi\" }"
+        ).unwrap();
+        let object = assembler.to_object();
+
+        let lines = Disassembler::from_object(&object, &image).disassemble();
+        assert_eq!(lines[0].label, Some(String::from("main")));
+        assert_eq!(lines[0].text, "jmp @end");
+        assert_eq!(lines[1].to_string(), "end:\n0006:    hlt");
+        assert_eq!(lines[2].to_string(), "msg:\n0007:    .string \"hi\"");
+    }
+
+    #[test]
+    fn test_disassemble_garbage() {
+        let lines = Disassembler::new(&[0xf7, 0x00]).disassemble();
+        assert_eq!(lines[0].text, ".byte 0xf7");
+        assert_eq!(lines[1].text, "hlt");
+    }
+}
@@ -1,6 +1,10 @@
 pub mod vm;
 pub mod repl;
 pub mod assembler;
+pub mod object;
+pub mod linker;
+pub mod disassembler;
+pub mod debugger;
 
 pub use repl::repl_asm::Repl;
 pub use vm::VM;
@@ -0,0 +1,309 @@
+//! The Linker combines several objects into one executable object.
+//!
+//! The code sections of the objects are placed one after another, in the
+//! order the objects were added, followed by all of their data sections.
+//! Each data section is padded to keep its own alignment, and the linked
+//! data section takes the largest of them.
+//! Every relocated field is then moved along with what it points to, and
+//! imported symbols are filled in with the address of the object that
+//! exports them.
+//!
+//! The linked object exports every symbol exported by its inputs. If one
+//! of them is called `main`, execution starts there, and otherwise at the
+//! code label `main` of the one object that keeps it local. Their local
+//! symbols are kept too, but are never used to fill in imports.
+
+use std::fmt;
+
+use crate::assembler::assembler::{Label, Symbol};
+use crate::object::{Object, Target};
+
+#[derive(Debug, Clone)]
+pub struct Linker {
+    objects: Vec<(String, Object)>,
+}
+
+impl Linker {
+    pub fn new() -> Self {
+        Self {
+            objects: Vec::new(),
+        }
+    }
+
+    /// Adds an object to be linked, named in errors by `name`.
+    pub fn add<S: Into<String>>(&mut self, name: S, object: Object) {
+        self.objects.push((name.into(), object));
+    }
+
+    /// Links every object added so far.
+    pub fn link(&self) -> Result<Object, LinkError> {
+        let code_len: usize = self.objects.iter().map(|(_, obj)| obj.code().len()).sum();
+        let data_align = self.objects.iter().map(|(_, obj)| obj.data_align()).max().unwrap_or(1);
+        let data_start = code_len.next_multiple_of(data_align);
+
+        // where each object's sections end up in the linked image
+        let mut bases = Vec::new();
+        let (mut code_base, mut data_base) = (0, data_start);
+        for (_, obj) in &self.objects {
+            data_base = data_base.next_multiple_of(obj.data_align());
+            bases.push((code_base, data_base));
+            code_base += obj.code().len();
+            data_base += obj.data().len();
+        }
+
+        let mut symbols: Vec<(Symbol, &str)> = Vec::new();
+        let mut locals = Vec::new();
+        let mut local_mains = Vec::new();
+        for ((file, obj), (code_base, data_base)) in self.objects.iter().zip(&bases) {
+            let moved = |sym: &Symbol| {
+                let offset = match sym.kind() {
+                    Label::Code => code_base + sym.offset(),
+                    Label::Data => data_base - data_start + sym.offset(),
+                };
+                Symbol::new(sym.name().to_string(), sym.kind(), offset)
+            };
+            locals.extend(obj.locals().iter().map(moved));
+            local_mains.extend(obj.locals().iter()
+                .filter(|sym| sym.name() == "main" && sym.kind() == Label::Code)
+                .map(|sym| (moved(sym), file)));
+            for sym in obj.symbols() {
+                if let Some((_, first)) = symbols.iter().find(|(s, _)| s.name() == sym.name()) {
+                    return Err(LinkError::DuplicateSymbol(
+                        sym.name().to_string(), first.to_string(), file.clone()
+                    ))
+                }
+                symbols.push((moved(sym), file));
+            }
+        }
+        let address_of = |name: &str| {
+            symbols.iter().find(|(sym, _)| sym.name() == name).map(|(sym, _)| match sym.kind() {
+                Label::Code => sym.offset(),
+                Label::Data => data_start + sym.offset(),
+            })
+        };
+
+        let mut code = Vec::new();
+        let mut data = Vec::new();
+        let mut strings = Vec::new();
+        for ((file, obj), (code_base, data_base)) in self.objects.iter().zip(&bases) {
+            for import in obj.imports() {
+                if address_of(import).is_none() {
+                    return Err(LinkError::UndefinedSymbol(import.clone(), file.clone()))
+                }
+            }
+
+            let mut obj_code = obj.code().to_vec();
+            let mut obj_data = obj.data().to_vec();
+            for reloc in obj.relocations() {
+                // how far the address in the field has to move
+                let delta = match &reloc.target {
+                    Target::Section(Label::Code) => *code_base as i64,
+                    Target::Section(Label::Data) => *data_base as i64 - obj.data_base() as i64,
+                    Target::Symbol(name) => address_of(name).unwrap() as i64,
+                };
+                let section = match reloc.section {
+                    Label::Code => &mut obj_code,
+                    Label::Data => &mut obj_data,
+                };
+                reloc.apply(section, delta);
+            }
+            data.resize(data_base - data_start, 0);
+            strings.extend(obj.strings().iter().map(|&(offset, len)| (data.len() + offset, len)));
+            code.extend(obj_code);
+            data.extend(obj_data);
+        }
+
+        let entry = match symbols.iter().find(|(sym, _)| sym.name() == "main") {
+            Some((sym, _)) => Some(sym),
+            None => match local_mains.as_slice() {
+                [] => None,
+                [(sym, _)] => Some(sym),
+                [(_, first), (_, second), ..] => {
+                    return Err(LinkError::AmbiguousEntry(first.to_string(), second.to_string()))
+                }
+            },
+        };
+        let entry = match entry {
+            Some(sym) if sym.kind() == Label::Data => return Err(LinkError::InvalidEntry),
+            Some(sym) => sym.offset(),
+            None => 0,
+        };
+
+        let symbols = symbols.into_iter().map(|(sym, _)| sym).collect();
+        let mut linked = Object::new(code, data, symbols, Vec::new(), Vec::new());
+        linked.set_entry(entry);
+        linked.set_locals(locals);
+        linked.set_strings(strings);
+        linked.set_data_align(data_align);
+        Ok(linked)
+    }
+}
+
+impl Default for Linker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LinkError {
+    /// The symbol is exported by both of the named objects.
+    DuplicateSymbol(String, String, String),
+    /// The symbol is imported by the named object but never exported.
+    UndefinedSymbol(String, String),
+    /// `main` is in a data section.
+    InvalidEntry,
+    /// Both of the named objects have a local `main`, and none exports one.
+    AmbiguousEntry(String, String),
+}
+
+impl std::error::Error for LinkError {}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::DuplicateSymbol(name, first, second) => {
+                write!(f, "Link Error: {} is exported by both {} and {}", name, first, second)
+            }
+            Self::UndefinedSymbol(name, file) => {
+                write!(f, "Link Error: {} is used by {} but never exported", name, file)
+            }
+            Self::InvalidEntry => {
+                write!(f, "Link Error: main must be in a code section")
+            }
+            Self::AmbiguousEntry(first, second) => {
+                write!(f, "Link Error: {} and {} both have a main that is not exported", first, second)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+    use crate::object;
+
+    fn assemble(code: &str) -> Object {
+        let mut assembler = Assembler::new();
+        assembler.allow_undefined(true);
+        assembler.assemble_source(code).unwrap();
+        assembler.to_object()
+    }
+
+    #[test]
+    fn test_link_and_run() {
+        let lib = assemble("\
+            .global @double @total\n\
+            double: { mul $1 2 $0\n\
+            mov $4 [total]\n\
+            add $0 $4 $0\n\
+            mov [total + 4] $0\n\
+            ret }\n\
+            .data\n\
+            total: { .word 100 0 }");
+        let main = assemble("\
+            .global @main @double @total @result\n\
+            main: { mov $1 [input]\n\
+            call @double\n\
+            mov $2 [@total + 4]\n\
+            mov $3 @result\n\
+            hlt }\n\
+            .data\n\
+            input: { .word 21 }\n\
+            result: { .word (input) }\n\
+            .string \"ok\"");
+        assert_eq!(main.imports(), &[String::from("double"), String::from("total")]);
+
+        let mut linker = Linker::new();
+        linker.add("lib.vdgo", lib);
+        linker.add("main.vdgo", main);
+        let linked = linker.link().unwrap();
+        assert_eq!(linked.entry(), linked.address_of("main").unwrap());
+        // after lib's two words and main's two
+        assert_eq!(linked.strings(), &[(16, 3)]);
+        assert!(linked.locals().contains(&Symbol::new(String::from("input"), Label::Data, 8)));
+
+        let mut test_vm = object::load(&linked.to_bytes()).unwrap();
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.test_register(0).unwrap(), 142);
+        assert_eq!(test_vm.test_register(2).unwrap(), 142);
+
+        // result holds the address of input, and $3 the address of result
+        let result = linked.address_of("result").unwrap();
+        assert_eq!(test_vm.test_register(3).unwrap(), result as i32);
+        assert_eq!(test_vm.load(result as i64).unwrap(), result as i32 - 4);
+    }
+
+    #[test]
+    fn test_link_keeps_alignment() {
+        let lib = assemble(".global @get\nget: { mov $0 [value]\nret }\n.data\n.byte 1\n.align 8\nvalue: { .word 42 }");
+        let main = assemble(".global @main @get\nmain: { call @get\nhlt }\n.data\n.byte 7");
+        let mut linker = Linker::new();
+        linker.add("main.vdgo", main);
+        linker.add("lib.vdgo", lib);
+        let linked = Object::from_bytes(&linker.link().unwrap().to_bytes()).unwrap();
+        assert_eq!(linked.data_align(), 8);
+
+        let labels = linked.labels();
+        let value = labels.iter().find(|(name, _)| name == "value").unwrap().1;
+        assert_eq!(value % 8, 0);
+        let mut test_vm = linked.to_vm().unwrap();
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.test_register(0).unwrap(), 42);
+    }
+
+    #[test]
+    fn test_local_main() {
+        let mut linker = Linker::new();
+        linker.add("lib.vdgo", assemble(".global @seven\nseven: { mov $0 7\nret }"));
+        linker.add("main.vdgo", assemble(".global @seven\nmain: { call @seven\nhlt }"));
+        let linked = linker.link().unwrap();
+        assert_eq!(linked.entry(), 9);
+
+        let mut test_vm = object::load(&linked.to_bytes()).unwrap();
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.test_register(0).unwrap(), 7);
+
+        // an exported main is used over a local one
+        linker.add("other.vdgo", assemble(".global @main\nmain: { hlt }"));
+        let linked = linker.link().unwrap();
+        assert_eq!(linked.entry(), linked.address_of("main").unwrap());
+    }
+
+    #[test]
+    fn test_link_errors() {
+        let mut linker = Linker::new();
+        linker.add("a.vdgo", assemble(".global @main\nmain: { hlt }"));
+        linker.add("b.vdgo", assemble(".global @main\nmain: { hlt }"));
+        assert_eq!(linker.link(), Err(LinkError::DuplicateSymbol(
+            String::from("main"), String::from("a.vdgo"), String::from("b.vdgo")
+        )));
+
+        let mut linker = Linker::new();
+        linker.add("a.vdgo", assemble(".global @missing\ncall @missing\nhlt"));
+        assert_eq!(linker.link(), Err(LinkError::UndefinedSymbol(
+            String::from("missing"), String::from("a.vdgo")
+        )));
+
+        // local labels don't clash, and can't be imported
+        let mut linker = Linker::new();
+        linker.add("a.vdgo", assemble(".global @main\nmain: { jmp @done }\ndone: { hlt }"));
+        linker.add("b.vdgo", assemble("done: { hlt }"));
+        assert_eq!(linker.link().unwrap().locals().len(), 2);
+        linker.add("c.vdgo", assemble(".global @done\njmp @done"));
+        assert_eq!(linker.link(), Err(LinkError::UndefinedSymbol(
+            String::from("done"), String::from("c.vdgo")
+        )));
+
+        let mut linker = Linker::new();
+        linker.add("a.vdgo", assemble("main: { hlt }"));
+        linker.add("b.vdgo", assemble("main: { hlt }"));
+        assert_eq!(linker.link(), Err(LinkError::AmbiguousEntry(
+            String::from("a.vdgo"), String::from("b.vdgo")
+        )));
+
+        let unlinked = assemble(".global @missing\ncall @missing").to_bytes();
+        assert!(matches!(object::load(&unlinked), Err(object::ObjectError::Unlinked(_))));
+    }
+}
@@ -0,0 +1,680 @@
+//! The on-disk format for assembled Oxidizer programs.
+//!
+//! An object file is laid out as follows, with all integers little endian:
+//!
+//! ```text
+//! magic          4 bytes   "VDGO"
+//! version        u16
+//! data align     u16       log2 of the data section's alignment
+//! entry          u32       offset into the code section
+//! code length    u32
+//! data length    u32
+//! pool length    u32
+//! symbol count   u32
+//! import count   u32
+//! reloc count    u32
+//! string count   u32
+//! code section
+//! data section   without its string literals
+//! string pool    NUL-terminated symbol names and string literals
+//! symbol table   (name offset u32, section u8, offset u32) per symbol
+//! import table   (name offset u32) per import
+//! reloc table    (section u8, offset u32, size u8, target u8, import u32) per relocation
+//! string table   (data offset u32, pool offset u32, length u32) per string literal
+//! ```
+//!
+//! The symbol table holds every label. Labels that are not exported with
+//! `.global` have the top bit of their section set, and are only kept so
+//! that tools such as the disassembler can name addresses. The import
+//! table names the symbols declared with `.global` but defined in
+//! another object. Every field that holds an address has a relocation,
+//! so that the linker can move the sections. A relocation's target is 0
+//! for the code section, 1 for the data section or 2 for an import, with
+//! the import's index in the import table.
+//!
+//! The literals from `.string` directives are kept in the string pool,
+//! each distinct one only once, rather than in the data section. The
+//! string table says where in the data section each of them goes, and
+//! they are put back in place when the object is read.
+//!
+//! When loaded, the data section is placed after the code section, at the
+//! first address that is a multiple of its alignment.
+//! An object with imports must be linked before it can be loaded.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use byteorder::*;
+
+use crate::assembler::assembler::{Label, Symbol};
+use crate::vm::VM;
+
+pub const MAGIC: [u8; 4] = *b"VDGO";
+pub const FORMAT_VERSION: u16 = 4;
+
+const HEADER_SIZE: usize = 40;
+const SYMBOL_SIZE: usize = 9;
+const IMPORT_SIZE: usize = 4;
+const RELOC_SIZE: usize = 11;
+const STRING_SIZE: usize = 12;
+/// Set in a symbol's section byte if it is not exported.
+const LOCAL_FLAG: u8 = 0x80;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Object {
+    entry: usize,
+    code: Vec<u8>,
+    data: Vec<u8>,
+    symbols: Vec<Symbol>,
+    /// Labels that are not exported, which other objects cannot use.
+    locals: Vec<Symbol>,
+    imports: Vec<String>,
+    relocations: Vec<Relocation>,
+    /// Where the string literals are in the data section, as an offset
+    /// and a length that includes the terminating NUL.
+    strings: Vec<(usize, usize)>,
+    /// What the address of the data section must be a multiple of,
+    /// always a power of two.
+    data_align: usize,
+}
+
+/// A field in one of the sections that holds an address,
+/// and so has to be adjusted when the sections are moved.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Relocation {
+    /// The section the field is in.
+    pub section: Label,
+    /// Where the field starts in its section.
+    pub offset: usize,
+    /// The size of the field: 4 bytes, or 8 for a plain pointer.
+    pub size: usize,
+    pub target: Target,
+}
+
+impl Relocation {
+    /// Moves the address held in the field by `delta`.
+    /// `section` is the contents of the section the field is in.
+    pub fn apply(&self, section: &mut [u8], delta: i64) {
+        let field = &mut section[self.offset..self.offset + self.size];
+        if self.size == 8 {
+            let value = LittleEndian::read_i64(field).wrapping_add(delta);
+            LittleEndian::write_i64(field, value);
+        } else {
+            let value = LittleEndian::read_i32(field).wrapping_add(delta as i32);
+            LittleEndian::write_i32(field, value);
+        }
+    }
+}
+
+/// What the address in a relocated field refers to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Target {
+    /// Something in this object's code or data section. The field holds
+    /// the address it has when this object is loaded on its own.
+    Section(Label),
+    /// An imported symbol. The field holds the offset from the symbol.
+    Symbol(String),
+}
+
+impl Object {
+    pub fn new(
+        code: Vec<u8>,
+        data: Vec<u8>,
+        symbols: Vec<Symbol>,
+        imports: Vec<String>,
+        relocations: Vec<Relocation>,
+    ) -> Self {
+        Self {
+            entry: 0,
+            code,
+            data,
+            symbols,
+            locals: Vec::new(),
+            imports,
+            relocations,
+            strings: Vec::new(),
+            data_align: 1,
+        }
+    }
+
+    pub fn code(&self) -> &[u8] {
+        &self.code
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    pub fn locals(&self) -> &[Symbol] {
+        &self.locals
+    }
+
+    pub fn set_locals(&mut self, locals: Vec<Symbol>) {
+        self.locals = locals;
+    }
+
+    pub fn imports(&self) -> &[String] {
+        &self.imports
+    }
+
+    pub fn relocations(&self) -> &[Relocation] {
+        &self.relocations
+    }
+
+    pub fn strings(&self) -> &[(usize, usize)] {
+        &self.strings
+    }
+
+    /// Marks which parts of the data section are string literals, given
+    /// as offsets and lengths in order, so they are kept in the string pool.
+    pub fn set_strings(&mut self, strings: Vec<(usize, usize)>) {
+        self.strings = strings;
+    }
+
+    pub fn data_align(&self) -> usize {
+        self.data_align
+    }
+
+    /// Sets the alignment of the data section, which must be a power of two.
+    pub fn set_data_align(&mut self, align: usize) {
+        self.data_align = align;
+    }
+
+    /// Returns where the data section starts once the object is loaded.
+    pub fn data_base(&self) -> usize {
+        self.code.len().next_multiple_of(self.data_align)
+    }
+
+    pub fn entry(&self) -> usize {
+        self.entry
+    }
+
+    /// Sets where execution starts, as an offset into the code section.
+    pub fn set_entry(&mut self, entry: usize) {
+        self.entry = entry;
+    }
+
+    /// Returns the address of an exported symbol once the object is loaded.
+    pub fn address_of(&self, name: &str) -> Option<usize> {
+        self.symbols.iter().find(|sym| sym.name() == name).map(|sym| self.load_address(sym))
+    }
+
+    /// Returns the name and loaded address of every label, exported or not.
+    pub fn labels(&self) -> Vec<(String, usize)> {
+        self.symbols.iter()
+            .chain(&self.locals)
+            .map(|sym| (sym.name().to_string(), self.load_address(sym)))
+            .collect()
+    }
+
+    fn load_address(&self, sym: &Symbol) -> usize {
+        match sym.kind() {
+            Label::Code => sym.offset(),
+            Label::Data => self.data_base() + sym.offset(),
+        }
+    }
+
+    /// Constructs a VM ready to run the object from its entry point.
+    pub fn to_vm(&self) -> Result<VM, ObjectError> {
+        if !self.imports.is_empty() {
+            return Err(ObjectError::Unlinked(self.imports.clone()))
+        }
+        let mut vm = VM::new(self.image());
+        vm.set_pc(self.entry);
+        Ok(vm)
+    }
+
+    /// Lays out the sections into a program that can be run by the VM.
+    pub fn image(&self) -> Vec<u8> {
+        let mut image = self.code.clone();
+        image.resize(self.data_base(), 0);
+        image.extend_from_slice(&self.data);
+        image
+    }
+
+    /// Serializes the object into its on-disk format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut pool = Pool::default();
+        let mut table = Vec::new();
+        let symbols = self.symbols.iter().map(|sym| (sym, 0)).chain(self.locals.iter().map(|sym| (sym, LOCAL_FLAG)));
+        for (sym, flags) in symbols {
+            table.write_u32::<LittleEndian>(pool.add_name(sym.name())).unwrap();
+            table.push(flags | match sym.kind() {
+                Label::Code => 0,
+                Label::Data => 1,
+            });
+            table.write_u32::<LittleEndian>(sym.offset() as u32).unwrap();
+        }
+        for name in &self.imports {
+            table.write_u32::<LittleEndian>(pool.add_name(name)).unwrap();
+        }
+        for reloc in &self.relocations {
+            table.push(match reloc.section {
+                Label::Code => 0,
+                Label::Data => 1,
+            });
+            table.write_u32::<LittleEndian>(reloc.offset as u32).unwrap();
+            table.push(reloc.size as u8);
+            let (target, import) = match &reloc.target {
+                Target::Section(Label::Code) => (0, 0),
+                Target::Section(Label::Data) => (1, 0),
+                Target::Symbol(name) => (2, self.imports.iter().position(|i| i == name)
+                    .expect("relocated symbols should be imported")),
+            };
+            table.push(target);
+            table.write_u32::<LittleEndian>(import as u32).unwrap();
+        }
+        let mut data = Vec::new();
+        let mut copied = 0;
+        for &(offset, len) in &self.strings {
+            data.extend_from_slice(&self.data[copied..offset]);
+            copied = offset + len;
+            table.write_u32::<LittleEndian>(offset as u32).unwrap();
+            table.write_u32::<LittleEndian>(pool.add(&self.data[offset..copied])).unwrap();
+            table.write_u32::<LittleEndian>(len as u32).unwrap();
+        }
+        data.extend_from_slice(&self.data[copied..]);
+
+        let mut bytes = MAGIC.to_vec();
+        bytes.write_u16::<LittleEndian>(FORMAT_VERSION).unwrap();
+        bytes.write_u16::<LittleEndian>(self.data_align.trailing_zeros() as u16).unwrap();
+        for field in &[
+            self.entry,
+            self.code.len(),
+            data.len(),
+            pool.bytes.len(),
+            self.symbols.len() + self.locals.len(),
+            self.imports.len(),
+            self.relocations.len(),
+            self.strings.len(),
+        ] {
+            bytes.write_u32::<LittleEndian>(*field as u32).unwrap();
+        }
+        bytes.extend_from_slice(&self.code);
+        bytes.extend(data);
+        bytes.extend(pool.bytes);
+        bytes.extend(table);
+
+        bytes
+    }
+
+    /// Parses and validates an object file.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ObjectError> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(ObjectError::Truncated)
+        }
+        if bytes[0..4] != MAGIC {
+            return Err(ObjectError::BadMagic)
+        }
+        let version = LittleEndian::read_u16(&bytes[4..6]);
+        if version != FORMAT_VERSION {
+            return Err(ObjectError::UnsupportedVersion(version))
+        }
+        let align_bits = LittleEndian::read_u16(&bytes[6..8]);
+        if align_bits > 31 {
+            return Err(ObjectError::InvalidAlignment(align_bits))
+        }
+        let field = |n: usize| LittleEndian::read_u32(&bytes[8 + n * 4..]) as usize;
+        let (entry, code_len, stored_len, pool_len, sym_count, import_count, reloc_count, string_count) =
+            (field(0), field(1), field(2), field(3), field(4), field(5), field(6), field(7));
+        let table_len = |count: usize, size: usize| count.checked_mul(size).ok_or(ObjectError::Truncated);
+
+        let mut rest = &bytes[HEADER_SIZE..];
+        let code = take(&mut rest, code_len)?.to_vec();
+        let mut stored = take(&mut rest, stored_len)?;
+        let pool = take(&mut rest, pool_len)?;
+        let table = take(&mut rest, table_len(sym_count, SYMBOL_SIZE)?)?;
+        let import_table = take(&mut rest, table_len(import_count, IMPORT_SIZE)?)?;
+        let reloc_table = take(&mut rest, table_len(reloc_count, RELOC_SIZE)?)?;
+        let string_table = take(&mut rest, table_len(string_count, STRING_SIZE)?)?;
+        if !rest.is_empty() {
+            return Err(ObjectError::TrailingBytes(rest.len()))
+        }
+        if entry != 0 && entry >= code_len {
+            return Err(ObjectError::InvalidEntry(entry))
+        }
+
+        // put the string literals back into the data section
+        let mut data = Vec::new();
+        let mut strings = Vec::new();
+        for (i, raw) in string_table.chunks(STRING_SIZE).enumerate() {
+            let offset = LittleEndian::read_u32(&raw[0..4]) as usize;
+            let pool_offset = LittleEndian::read_u32(&raw[4..8]) as usize;
+            let len = LittleEndian::read_u32(&raw[8..12]) as usize;
+            let before = offset.checked_sub(data.len())
+                .and_then(|gap| take(&mut stored, gap).ok())
+                .ok_or(ObjectError::InvalidString(i))?;
+            let text = pool.get(pool_offset..)
+                .and_then(|text| text.get(..len))
+                .ok_or(ObjectError::InvalidString(i))?;
+            data.extend_from_slice(before);
+            data.extend_from_slice(text);
+            strings.push((offset, len));
+        }
+        data.extend_from_slice(stored);
+        let data_len = data.len();
+
+        let mut symbols = Vec::new();
+        let mut locals = Vec::new();
+        for raw in table.chunks(SYMBOL_SIZE) {
+            let name_offset = LittleEndian::read_u32(&raw[0..4]) as usize;
+            let offset = LittleEndian::read_u32(&raw[5..9]) as usize;
+            let name = read_name(pool, name_offset)?;
+            let (kind, section_len) = match raw[4] & !LOCAL_FLAG {
+                0 => (Label::Code, code_len),
+                1 => (Label::Data, data_len),
+                _ => return Err(ObjectError::InvalidSymbol(name)),
+            };
+            if offset > section_len {
+                return Err(ObjectError::InvalidSymbol(name))
+            }
+            let symbol = Symbol::new(name, kind, offset);
+            if raw[4] & LOCAL_FLAG != 0 {
+                locals.push(symbol);
+            } else {
+                symbols.push(symbol);
+            }
+        }
+
+        let imports = import_table.chunks(IMPORT_SIZE)
+            .map(|raw| read_name(pool, LittleEndian::read_u32(raw) as usize))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut relocations = Vec::new();
+        for (i, raw) in reloc_table.chunks(RELOC_SIZE).enumerate() {
+            let offset = LittleEndian::read_u32(&raw[1..5]) as usize;
+            let size = raw[5] as usize;
+            let import = LittleEndian::read_u32(&raw[7..11]) as usize;
+            let (section, section_len) = match raw[0] {
+                0 => (Label::Code, code_len),
+                1 => (Label::Data, data_len),
+                _ => return Err(ObjectError::InvalidRelocation(i)),
+            };
+            let target = match raw[6] {
+                0 => Target::Section(Label::Code),
+                1 => Target::Section(Label::Data),
+                2 if import < imports.len() => Target::Symbol(imports[import].clone()),
+                _ => return Err(ObjectError::InvalidRelocation(i)),
+            };
+            if (size != 4 && size != 8) || offset + size > section_len {
+                return Err(ObjectError::InvalidRelocation(i))
+            }
+            relocations.push(Relocation {
+                section,
+                offset,
+                size,
+                target,
+            });
+        }
+
+        Ok(Self {
+            entry,
+            code,
+            data,
+            symbols,
+            locals,
+            imports,
+            relocations,
+            strings,
+            data_align: 1 << align_bits,
+        })
+    }
+}
+
+/// The string pool being built for an object file, which stores each
+/// distinct name or string literal once.
+#[derive(Default)]
+struct Pool {
+    bytes: Vec<u8>,
+    offsets: HashMap<Vec<u8>, u32>,
+}
+
+impl Pool {
+    /// Adds some bytes to the pool and returns their offset.
+    fn add(&mut self, bytes: &[u8]) -> u32 {
+        if let Some(&offset) = self.offsets.get(bytes) {
+            return offset
+        }
+        let offset = self.bytes.len() as u32;
+        self.bytes.extend_from_slice(bytes);
+        self.offsets.insert(bytes.to_vec(), offset);
+        offset
+    }
+
+    fn add_name(&mut self, name: &str) -> u32 {
+        let mut bytes = name.as_bytes().to_vec();
+        bytes.push(0);
+        self.add(&bytes)
+    }
+}
+
+/// Validates an object file and constructs a VM ready to run it.
+pub fn load(bytes: &[u8]) -> Result<VM, ObjectError> {
+    Object::from_bytes(bytes)?.to_vm()
+}
+
+fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Result<&'a [u8], ObjectError> {
+    if bytes.len() < len {
+        return Err(ObjectError::Truncated)
+    }
+    let (section, rest) = bytes.split_at(len);
+    *bytes = rest;
+    Ok(section)
+}
+
+fn read_name(pool: &[u8], offset: usize) -> Result<String, ObjectError> {
+    let bytes = pool.get(offset..).ok_or(ObjectError::InvalidStringPool)?;
+    let end = bytes.iter().position(|&b| b == 0).ok_or(ObjectError::InvalidStringPool)?;
+    String::from_utf8(bytes[..end].to_vec()).map_err(|_| ObjectError::InvalidStringPool)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ObjectError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    Truncated,
+    TrailingBytes(usize),
+    InvalidEntry(usize),
+    InvalidSymbol(String),
+    InvalidRelocation(usize),
+    InvalidStringPool,
+    InvalidString(usize),
+    InvalidAlignment(u16),
+    Unlinked(Vec<String>),
+}
+
+impl std::error::Error for ObjectError {}
+
+impl fmt::Display for ObjectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::BadMagic => {
+                write!(f, "Object Error: not an Oxidizer object file")
+            }
+            Self::UnsupportedVersion(version) => {
+                write!(f,
+                    "Object Error: unsupported format version {} (expected {})",
+                    version, FORMAT_VERSION
+                )
+            }
+            Self::Truncated => {
+                write!(f, "Object Error: file is truncated")
+            }
+            Self::TrailingBytes(len) => {
+                write!(f, "Object Error: {} unexpected bytes at end of file", len)
+            }
+            Self::InvalidEntry(entry) => {
+                write!(f, "Object Error: entry point {} is outside the code section", entry)
+            }
+            Self::InvalidSymbol(name) => {
+                write!(f, "Object Error: symbol {} points outside its section", name)
+            }
+            Self::InvalidRelocation(index) => {
+                write!(f, "Object Error: relocation {} is malformed", index)
+            }
+            Self::InvalidStringPool => {
+                write!(f, "Object Error: malformed string pool")
+            }
+            Self::InvalidString(index) => {
+                write!(f, "Object Error: string literal {} is malformed", index)
+            }
+            Self::InvalidAlignment(bits) => {
+                write!(f, "Object Error: data alignment 2^{} is too large", bits)
+            }
+            Self::Unlinked(names) => {
+                write!(f,
+                    "Object Error: {} must be linked in from another object",
+                    names.join(", ")
+                )
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::{Assembler, Lexer, Parser};
+
+    fn assemble(code: &str) -> Object {
+        let mut lexer = Lexer::new();
+        let mut parser = Parser::new();
+        let parsed = parser.parse(lexer.tokenize(code).unwrap()).unwrap();
+        let mut assembler = Assembler::new();
+        assembler.assemble(parsed).unwrap();
+        assembler.to_object()
+    }
+
+    #[test]
+    fn test_object_round_trip() {
+        let object = assemble(
+            ".global @main @msg\nmain: { jmp @end }\nend: { hlt }\n.data\nmsg: { .string \"hi\" }"
+        );
+
+        assert_eq!(object.symbols(), &[
+            Symbol::new(String::from("main"), Label::Code, 0),
+            Symbol::new(String::from("msg"), Label::Data, 0),
+        ]);
+        assert_eq!(object.data(), b"hi\0");
+        assert_eq!(object.locals(), &[Symbol::new(String::from("end"), Label::Code, 6)]);
+        assert_eq!(object.address_of("end"), None);
+        assert!(object.labels().contains(&(String::from("end"), 6)));
+
+        let bytes = object.to_bytes();
+        assert_eq!(&bytes[0..4], b"VDGO");
+        assert_eq!(Object::from_bytes(&bytes), Ok(object.clone()));
+        assert_eq!(object.address_of("msg"), Some(object.code().len()));
+    }
+
+    #[test]
+    fn test_strings_in_pool() {
+        let object = assemble(
+            ".global @main
+main: { hlt }
+.data
+.string \"main\"
+.word 7
+.string \"hi\"
+.string \"hi\""
+        );
+        assert_eq!(object.strings(), &[(0, 5), (9, 3), (12, 3)]);
+
+        // the literals are only stored once, sharing the symbol's name
+        let bytes = object.to_bytes();
+        let stored = &bytes[HEADER_SIZE + object.code().len()..];
+        assert_eq!(&stored[..4], &[7, 0, 0, 0]);
+        assert_eq!(&stored[4..12], b"main\0hi\0");
+        let read = Object::from_bytes(&bytes).unwrap();
+        assert_eq!(read.data(), b"main\0\x07\0\0\0hi\0hi\0");
+        assert_eq!(read, object);
+
+        let mut bad_string = bytes;
+        let len = bad_string.len();
+        bad_string[len - 12] = 3;
+        assert_eq!(Object::from_bytes(&bad_string), Err(ObjectError::InvalidString(2)));
+    }
+
+    #[test]
+    fn test_relocations_round_trip() {
+        let mut lexer = Lexer::new();
+        let mut parser = Parser::new();
+        let code = ".global @ext\nmov $1 [@ext + 4]\njmp @end\nend: { hlt }\n.data\n.word (end + 1)";
+        let parsed = parser.parse(lexer.tokenize(code).unwrap()).unwrap();
+        let mut assembler = Assembler::new();
+        assembler.allow_undefined(true);
+        assembler.assemble(parsed).unwrap();
+        let object = assembler.to_object();
+
+        assert_eq!(object.imports(), &[String::from("ext")]);
+        assert_eq!(object.relocations(), &[
+            Relocation { section: Label::Code, offset: 4, size: 8, target: Target::Symbol(String::from("ext")) },
+            Relocation { section: Label::Code, offset: 14, size: 4, target: Target::Section(Label::Code) },
+            Relocation { section: Label::Data, offset: 0, size: 4, target: Target::Section(Label::Code) },
+        ]);
+        assert_eq!(Object::from_bytes(&object.to_bytes()), Ok(object.clone()));
+
+        let mut bad_reloc = object.to_bytes();
+        let len = bad_reloc.len();
+        bad_reloc[len - 6] = 9;
+        assert_eq!(Object::from_bytes(&bad_reloc), Err(ObjectError::InvalidRelocation(2)));
+    }
+
+    #[test]
+    fn test_load_and_run() {
+        let bytes = assemble("add 2 3 $4\nhlt").to_bytes();
+        let mut test_vm = load(&bytes).unwrap();
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.test_register(4).unwrap(), 5);
+    }
+
+    #[test]
+    fn test_load_from_main() {
+        let object = assemble("mov $1 1\nhlt\nmain: { mov $1 2\nhlt }");
+        assert_eq!(object.entry(), 9);
+        let mut test_vm = load(&object.to_bytes()).unwrap();
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.test_register(1).unwrap(), 2);
+
+        let object = assemble("hlt\n.data\nmain: { .word 1 }");
+        assert_eq!(object.entry(), 0);
+    }
+
+    #[test]
+    fn test_invalid_objects() {
+        let bytes = assemble(".global @main\nmain: { hlt }").to_bytes();
+
+        let mut bad_magic = bytes.clone();
+        bad_magic[0] = b'X';
+        assert_eq!(Object::from_bytes(&bad_magic), Err(ObjectError::BadMagic));
+
+        let mut bad_version = bytes.clone();
+        bad_version[4] = 1;
+        assert_eq!(Object::from_bytes(&bad_version), Err(ObjectError::UnsupportedVersion(1)));
+
+        assert_eq!(Object::from_bytes(&bytes[..bytes.len() - 1]), Err(ObjectError::Truncated));
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(Object::from_bytes(&trailing), Err(ObjectError::TrailingBytes(1)));
+
+        let mut bad_symbol = bytes.clone();
+        let len = bad_symbol.len();
+        bad_symbol[len - 4] = 0xff;
+        assert_eq!(
+            Object::from_bytes(&bad_symbol),
+            Err(ObjectError::InvalidSymbol(String::from("main")))
+        );
+
+        let mut bad_align = bytes.clone();
+        bad_align[6] = 32;
+        assert_eq!(Object::from_bytes(&bad_align), Err(ObjectError::InvalidAlignment(32)));
+
+        let mut bad_entry = bytes;
+        bad_entry[8] = 50;
+        assert_eq!(Object::from_bytes(&bad_entry), Err(ObjectError::InvalidEntry(50)));
+    }
+}
@@ -1,227 +1,232 @@
-use byteorder::*;
+//! Splits the lines typed into the REPL into shell commands and assembly.
+//!
+//! Shell commands start with `.` like directives do, so only the names
+//! listed in `ReplCmd` are taken as commands. Everything else is handed
+//! to the assembler, which lexes, parses and encodes it exactly as it
+//! would a line in a file.
+//!
+//! A line that opens a block is not assembled on its own. The REPL keeps
+//! reading lines until the block is closed, or until an empty line.
+
 use std::io;
 use std::fmt;
-
-use crate::vm::instruction::Opcode;
-
-pub type ParseResult<T> = Result<T, AsmLexErr>;
-
-pub struct AsmLexer {
-    pos: usize,
-    state: Token,
-}
-
-impl AsmLexer {
-    pub fn new() -> Self {
-        Self {
-            pos: 0,
-            state: Token::LineStart,
-        }
-    }
-
-    pub fn parse(&mut self, line: String) -> ParseResult<Option<Executable>> {
-        let inst: Vec<&str> = line.trim().split(' ').collect();
-        let len = inst.len() as u8;
-        if inst.is_empty() {
-            return Ok(None)
-        }
-        if inst[0].starts_with('.') {
-            if let Ok(cmd) = self.parse_command(inst) {
-                return Ok(Some(Executable::Command(cmd)));
-            } else {
-                eprintln!("Invalid command, type .help for a list of commands");
-                return Ok(None)
-            }
-        }
-        let mut code: Vec<u8> = Vec::new();
-        match inst[0] {
-            "hlt"  => {
-                if len > 1 {
-                    return Err(AsmLexErr::IncorrectOperandNo(0, len - 1))
-                }
-                code.push(Opcode::Hlt as u8);
-            }
-            "mov"  => {
-                if len != 3 {
-                    return Err(AsmLexErr::IncorrectOperandNo(2, len - 1))
-                }
-                code.push(Opcode::Mov as u8);
-                code.push(parse_as_register(&inst[1][1..])? as u8);
-                if inst[2].starts_with("&") { //if is pointer
-                    code.push(1);
-                    code.extend_from_slice(&u32_to_bytes(
-                        parse_as_number(&inst[2][1..])? as u32
-                    ));
-                } else if inst[2].starts_with("$") { //is register
-                    code.push(2);
-                    code.push(parse_as_register(&inst[2][1..])? as u8);
-                } else { //is literal
-                    code.push(0);
-                    code.extend_from_slice(&i32_to_bytes(
-                        parse_as_number(&inst[2])?
-                    ));
-                }
-            }
-            "jmp"  => {
-                if len != 2 {
-                    return Err(AsmLexErr::IncorrectOperandNo(1, len - 1))
-                }
-                code.push(Opcode::Jmp as u8);
-                if inst[1].starts_with("&") { //is pointer
-                    code.push(1);
-                    code.extend_from_slice(&u32_to_bytes(
-                        parse_as_number(&inst[1][1..])? as u32
-                    ));
-                } else if let Ok(num) = parse_as_number(inst[1]) {
-                    //is literal
-                    code.push(0);
-                    code.extend_from_slice(&i32_to_bytes(num));
-                } else { //is label
-                    unimplemented!("jumping to labels not yet implemented")
-                }
-            }
-            op @ "jmpf" | op @ "jmpb" => {
-                if len != 2 {
-                    return Err(AsmLexErr::IncorrectOperandNo(1, len - 1))
-                }
-                code.push(if op == "jmpf" {
-                        Opcode::Jmpf as u8
-                    } else {
-                        Opcode::Jmpb as u8
-                    }
-                );
-                if let Ok(num) = parse_as_number(inst[1]) {
-                    code.extend_from_slice(&i32_to_bytes(num));
-                } else {
-                    return Err(AsmLexErr::UnexpectedOperand(inst[1].to_string()))
-                }
-            }
-            "cmp"  => {}
-            "lt"   => {}
-            "gt"   => {}
-            "le"   => {}
-            "ge"   => {}
-            "jeq"  => {}
-            "jne"  => {}
-            "aloc" => {}
-            "dalc" => {}
-            "add"  => {}
-            "sub"  => {}
-            "mul"  => {}
-            "div"  => {}
-            "igl"  => {}
-            _ => {}
-        }
-        Ok(Some(Executable::Instruction(code)))
-    }
-
-    fn parse_command(&mut self, cmd: Vec<&str>) -> Result<ReplCmd, ()> {
-        match cmd[0] {
-            ".info" => { return Ok(ReplCmd::Info) }
-            ".registers" => { return Ok(ReplCmd::Registers) }
-            ".program" => { return Ok(ReplCmd::Program) }
-            ".quit" => { return Ok(ReplCmd::Quit) }
-            ".help" => { return Ok(ReplCmd::Help) }
-            _ => {
-                return Err(())
-            }
-        }
-    }
-}
-
-fn parse_as_number(text: &str) -> Result<i32, AsmLexErr> {
-    if let Ok(num) = text.parse::<i32>() {
-        return Ok(num)
-    } else {
-        return Err(AsmLexErr::CouldNotParse(text.to_string()))
+use std::path::PathBuf;
+
+use crate::assembler::{AsmParseErr, Directive, Lexer, Token};
+use crate::debugger::DebugError;
+use crate::vm::vm::VMError;
+
+pub type ParseResult<T> = Result<T, ReplError>;
+
+/// Works out what a line of input is.
+pub fn parse_line(line: &str) -> ParseResult<Option<Executable>> {
+    let line = line.trim();
+    let mut words = line.split_whitespace();
+    let first = match words.next() {
+        Some(first) => first,
+        None => return Ok(None),
+    };
+    match ReplCmd::from_words(first, words.collect()) {
+        Some(cmd) => Ok(Some(Executable::Command(cmd?))),
+        None => Ok(Some(Executable::Assembly(line.to_string()))),
     }
 }
 
-fn parse_as_register(text: &str) -> Result<u8, AsmLexErr> {
-    if let Ok(num) = text.parse::<u32>() {
-        if num > 31 {
-            return Err(AsmLexErr::InvalidRegister(num))
+/// Returns false if the source stops partway through a label's block,
+/// a macro definition, an expression or a comment, so that more lines
+/// should be read before it is assembled.
+pub fn is_complete(source: &str) -> bool {
+    let tokens = match Lexer::new().tokenize(source) {
+        Ok(tokens) => tokens,
+        Err(AsmParseErr::UnexpectedEOF(_)) => return false,
+        Err(_) => return true,
+    };
+    let mut depth = 0;
+    for token in tokens {
+        match token {
+            Token::LabelDeclStart(..) | Token::Directive(Directive::Macro, _) => depth += 1,
+            Token::LabelDeclEnd(_) | Token::Directive(Directive::Endm, _) => depth -= 1,
+            _ => {}
         }
-        return Ok(num as u8)
-    } else {
-        return Err(AsmLexErr::CouldNotParse(text.to_string()))
     }
-}
-
-fn i32_to_bytes(num: i32) -> [u8; 4] {
-    let mut buf = [0, 0, 0, 0];
-    buf.as_mut().write_i32::<LittleEndian>(num).unwrap();
-    buf
-}
-
-fn u32_to_bytes(num: u32) -> [u8; 4] {
-    let mut buf = [0, 0, 0, 0];
-    buf.as_mut().write_u32::<LittleEndian>(num).unwrap();
-    buf
-}
-
-#[derive(Debug, Clone, Copy)]
-#[allow(dead_code)]
-pub enum Token {
-    LineStart,
-    Opcode(u8),
-    Pointer(usize),
-    Register(usize),
-    Literal(i32),
+    depth <= 0
 }
 
 #[derive(Debug, Clone)]
 pub enum Executable {
-    Instruction(Vec<u8>),
+    Assembly(String),
     Command(ReplCmd),
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ReplCmd {
     Info,
     Registers,
     Program,
     Quit,
     Help,
+    Load(PathBuf),
+    Save(PathBuf),
+    Reset,
+    Step,
+    Run,
+    Debug,
+}
+
+/// The usage and description of every command, as listed by `.help`.
+pub const COMMANDS: [(&str, &str); 11] = [
+    (".help", "Lists the commands"),
+    (".info", "Shows the program counter, flags, memory and program size"),
+    (".registers", "Shows the contents of every register"),
+    (".program", "Disassembles the program entered so far"),
+    (".load <file.vasm>", "Assembles a file onto the end of the program"),
+    (".save <file.vbc>", "Saves the program as an object file for `verdigris run`"),
+    (".step", "Runs the next instruction"),
+    (".run", "Runs until the VM halts or reaches the end of the program"),
+    (".debug", "Starts the debugger at the current instruction"),
+    (".reset", "Clears the program, labels and VM"),
+    (".quit", "Exits the shell"),
+];
+
+impl ReplCmd {
+    /// Returns the command called `name`, or `None` if there is no such
+    /// command and the line should be treated as assembly instead.
+    fn from_words(name: &str, args: Vec<&str>) -> Option<ParseResult<Self>> {
+        let cmd = match name {
+            ".info" => Self::Info,
+            ".registers" => Self::Registers,
+            ".program" => Self::Program,
+            ".quit" => Self::Quit,
+            ".help" => Self::Help,
+            ".reset" => Self::Reset,
+            ".step" => Self::Step,
+            ".run" => Self::Run,
+            ".debug" => Self::Debug,
+            ".load" | ".save" => {
+                return Some(match args.as_slice() {
+                    [path] if name == ".load" => Ok(Self::Load(PathBuf::from(path))),
+                    [path] => Ok(Self::Save(PathBuf::from(path))),
+                    _ => Err(ReplError::Usage(name.to_string())),
+                })
+            }
+            _ => return None,
+        };
+        if !args.is_empty() {
+            return Some(Err(ReplError::Usage(name.to_string())))
+        }
+        Some(Ok(cmd))
+    }
 }
 
 #[derive(Debug, Clone)]
-pub enum AsmLexErr {
+pub enum ReplError {
     ReadError,
-    UnexpectedOperand(String),
-    IncorrectOperandNo(u8, u8),
-    InvalidRegister(u32),
-    CouldNotParse(String),
+    Asm(AsmParseErr),
+    Vm(VMError),
+    DataSection,
+    /// A file given to `.load` has a data section.
+    DataFile(String),
+    Usage(String),
+    File(String, String),
+    Debug(DebugError),
 }
 
-impl std::error::Error for AsmLexErr {}
+impl std::error::Error for ReplError {}
 
-impl fmt::Display for AsmLexErr {
+impl fmt::Display for ReplError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Self::ReadError => {
                 write!(f, "Error: unable to read line")
             }
-            Self::UnexpectedOperand(op) => {
-                write!(f, "Error: Unexpected operand {}", op)
+            Self::Asm(err) => {
+                write!(f, "{}", err)
+            }
+            Self::Vm(err) => {
+                write!(f, "{}", err)
+            }
+            Self::DataSection => {
+                write!(f, "Error: the data section cannot be used in the REPL, since new code is added to the end of the program")
             }
-            Self::IncorrectOperandNo(expected, given) => {
-                write!(f, "Error: {} operands expected, found {}",
-                    expected, given
+            Self::DataFile(path) => {
+                write!(f,
+                    "Error: {} has a data section, which the REPL cannot hold since new code is added \
+                     to the end of the program; use `verdigris run` or `verdigris debug` instead",
+                    path
                 )
             }
-            Self::InvalidRegister(num) => {
-                write!(f, "Error: invalid register {}", num)
+            Self::Usage(name) => {
+                let usage = COMMANDS.iter()
+                    .map(|(usage, _)| *usage)
+                    .find(|usage| usage.split(' ').next() == Some(name))
+                    .unwrap_or(name);
+                write!(f, "Usage: {}", usage)
             }
-            Self::CouldNotParse(text) => {
-                write!(f, "Error: could not parse `{}` as a number", text)
+            Self::File(path, err) => {
+                write!(f, "Error: {}: {}", path, err)
+            }
+            Self::Debug(err) => {
+                write!(f, "{}", err)
             }
         }
     }
 }
 
-impl From<io::Error> for AsmLexErr {
+impl From<io::Error> for ReplError {
     fn from(_from: io::Error) -> Self {
         Self::ReadError
     }
-}
\ No newline at end of file
+}
+
+impl From<AsmParseErr> for ReplError {
+    fn from(from: AsmParseErr) -> Self {
+        Self::Asm(from)
+    }
+}
+
+impl From<DebugError> for ReplError {
+    fn from(from: DebugError) -> Self {
+        Self::Debug(from)
+    }
+}
+
+impl From<VMError> for ReplError {
+    fn from(from: VMError) -> Self {
+        Self::Vm(from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn test_parse_line() {
+        assert!(matches!(parse_line("   "), Ok(None)));
+        assert!(matches!(parse_line(".registers"), Ok(Some(Executable::Command(ReplCmd::Registers)))));
+        assert!(matches!(parse_line("hlt ; stop"), Ok(Some(Executable::Assembly(_)))));
+        assert!(matches!(parse_line(".word 5"), Ok(Some(Executable::Assembly(_)))));
+        assert!(matches!(
+            parse_line(".load  lib/maths.vasm"),
+            Ok(Some(Executable::Command(ReplCmd::Load(path)))) if path.as_path() == Path::new("lib/maths.vasm")
+        ));
+        assert!(matches!(parse_line(".save"), Err(ReplError::Usage(_))));
+        assert!(matches!(parse_line(".run now"), Err(ReplError::Usage(_))));
+        assert_eq!(parse_line(".save").unwrap_err().to_string(), "Usage: .save <file.vbc>");
+    }
+
+    #[test]
+    fn test_is_complete() {
+        assert!(is_complete("mov $1 2"));
+        assert!(!is_complete("loop: {\nadd $1 1 $1"));
+        assert!(is_complete("loop: {\nadd $1 1 $1\n}"));
+        assert!(!is_complete("outer: { inner: { hlt }"));
+        assert!(!is_complete(".macro twice reg\nadd reg reg reg"));
+        assert!(is_complete(".macro twice reg\nadd reg reg reg\n.endm"));
+        assert!(!is_complete("mov $1 (2 +"));
+        assert!(!is_complete("hlt /* still"));
+        // errors other than running out of input are reported straight away
+        assert!(is_complete("mov $99 1 loop: {"));
+    }
+}
@@ -1,4 +1,5 @@
 pub mod repl_asm;
 pub mod lexer;
+pub mod session;
 
 pub use repl_asm as asm;
\ No newline at end of file
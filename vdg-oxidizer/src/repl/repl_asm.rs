@@ -1,27 +1,42 @@
+use std::fs;
+
 use linefeed::{
-    Interface, 
+    Interface,
     terminal::DefaultTerminal,
     reader::ReadResult,
 };
 
+use crate::debugger::{Debugger, RUN_LIMIT};
+use crate::disassembler::Disassembler;
+use crate::object::Object;
 use crate::vm::VM;
 use crate::repl::lexer::*;
+use crate::repl::session::Session;
+
+const PROMPT: &str = ">>> ";
+const CONTINUE_PROMPT: &str = "... ";
 
 /// The repl for the low-level IR (assembly) of Verdigris.
+///
+/// Assembly typed into the repl is added to the end of the program,
+/// and is run with `.step` and `.run`.
 pub struct Repl {
     vm: VM,
     interface: Interface<DefaultTerminal>,
-    lexer: AsmLexer,
+    session: Session,
+    /// The lines of a block that has not been closed yet.
+    buffer: String,
 }
 
 impl Repl {
     pub fn new() -> Self {
         let lr = Interface::new("vdg-asm").unwrap();
-        lr.set_prompt(">>> ").unwrap();
+        lr.set_prompt(PROMPT).unwrap();
         Self {
             vm: VM::new(vec![]),
             interface: lr,
-            lexer: AsmLexer::new(),
+            session: Session::new(),
+            buffer: String::new(),
         }
     }
 
@@ -29,44 +44,91 @@ impl Repl {
         println!("Oxidizer Shell v0.1.0");
         println!("Type .help for a list of commands.");
         loop {
-            match self.read_line() {
-                Ok(exec) => {
-                    if let Some(exec) = exec {
-                        match exec {
-                            Executable::Command(cmd) => {
-                                self.exec_cmd(cmd);
-                                continue
-                            }
-                            Executable::Instruction(bytes) => {
-                                self.vm.add_bytes(bytes);
-                                if let Ok(done) = self.vm.run_once() {
-                                    if done {
-                                        std::process::exit(0)
-                                    } 
-                                }
-                            }
-                        }
-                    } else {continue}
-                }
-                Err(e) => {
-                    eprintln!("{}", e);
-                    continue
+            let result = match self.read_line() {
+                Ok(Some(Executable::Command(cmd))) => self.exec_cmd(cmd),
+                Ok(Some(Executable::Assembly(source))) => {
+                    self.session.assemble(&source).map(|code| self.vm.add_bytes(code))
                 }
+                Ok(None) => Ok(()),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                eprintln!("{}", e);
+            }
+        }
+    }
+
+    /// Reads a line, or the rest of a block if one is open.
+    /// An empty line assembles an open block as it is.
+    fn read_line(&mut self) -> Result<Option<Executable>, ReplError> {
+        let line = match self.interface.read_line()? {
+            ReadResult::Input(line) => line,
+            ReadResult::Eof if self.buffer.is_empty() => std::process::exit(0),
+            ReadResult::Eof => {
+                // abandon the open block
+                println!();
+                self.buffer.clear();
+                self.interface.set_prompt(PROMPT)?;
+                return Ok(None)
             }
+            ReadResult::Signal(_) => return Ok(None),
+        };
+        self.interface.add_history(line.clone());
+
+        if self.buffer.is_empty() {
+            match parse_line(&line)? {
+                Some(Executable::Assembly(_)) => {}
+                exec => return Ok(exec),
+            }
+        } else if line.trim().is_empty() {
+            self.interface.set_prompt(PROMPT)?;
+            return Ok(Some(Executable::Assembly(std::mem::take(&mut self.buffer))))
+        }
+
+        self.buffer.push_str(&line);
+        self.buffer.push('\n');
+        if !is_complete(&self.buffer) {
+            self.interface.set_prompt(CONTINUE_PROMPT)?;
+            return Ok(None)
+        }
+        self.interface.set_prompt(PROMPT)?;
+        Ok(Some(Executable::Assembly(std::mem::take(&mut self.buffer))))
+    }
+
+    /// Runs the next instruction, printing it first.
+    fn step(&mut self) -> Result<(), ReplError> {
+        let pc = self.vm.pc();
+        if let Some(line) = Disassembler::new(self.vm.program())
+            .disassemble()
+            .into_iter()
+            .find(|line| line.address == pc)
+        {
+            println!("{}", line);
         }
+        if self.vm.run_once()? {
+            println!("Halting VM");
+        }
+        Ok(())
     }
 
-    fn read_line(&mut self) -> Result<Option<Executable>, AsmLexErr> {
-        if let ReadResult::Input(line) = self.interface.read_line()? {
-            self.interface.add_history(line.clone());
-            if let Some(exec) = self.lexer.parse(line.to_lowercase())? {
-                return Ok(Some(exec))
+    /// Runs until the program halts or reaches the end, stopping early
+    /// so that an infinite loop cannot hang the REPL.
+    fn run_program(&mut self) -> Result<(), ReplError> {
+        for _ in 0..RUN_LIMIT {
+            if self.vm.pc() >= self.vm.program().len() {
+                return Ok(())
+            }
+            if self.vm.run_once()? {
+                println!("Halting VM");
+                return Ok(())
             }
         }
-        return Ok(None)
+        println!("Stopped after {} instructions, type .run to keep going", RUN_LIMIT);
+        Ok(())
     }
 
-    fn exec_cmd(&self, cmd: ReplCmd) {
+    fn exec_cmd(&mut self, cmd: ReplCmd) -> Result<(), ReplError> {
+        let at_end = self.vm.pc() >= self.vm.program().len();
         match cmd {
             ReplCmd::Registers => {
                 self.vm.dump_registers();
@@ -75,15 +137,71 @@ impl Repl {
                 self.vm.dump_program();
             }
             ReplCmd::Help => {
-                unimplemented!()
+                println!("Assembly entered here is added to the end of the program.");
+                println!("Commands:");
+                for (usage, description) in COMMANDS.iter() {
+                    println!("  {:<20} {}", usage, description);
+                }
             }
             ReplCmd::Info => {
-                unimplemented!()
+                let memory = self.vm.memory();
+                println!("pc: {:#06x}", self.vm.pc());
+                println!("eq: {}", self.vm.eq_flag());
+                println!("rem: {}", self.vm.remainder());
+                println!("heap: {} bytes", memory.heap_size());
+                println!("stack: {} bytes", memory.stack_size());
+                println!("program: {} bytes", self.vm.program().len());
+            }
+            ReplCmd::Load(path) => {
+                let source = fs::read_to_string(&path)
+                    .map_err(|e| ReplError::File(path.display().to_string(), e.to_string()))?;
+                let code = self.session.assemble_file(&path, &source).map_err(|err| match err {
+                    ReplError::DataSection => ReplError::DataFile(path.display().to_string()),
+                    err => err,
+                })?;
+                println!("Loaded {} bytes from {}", code.len(), path.display());
+                self.vm.add_bytes(code);
+            }
+            ReplCmd::Save(path) => {
+                let object = Object::new(self.vm.program().to_vec(), Vec::new(), Vec::new(), Vec::new(), Vec::new());
+                fs::write(&path, object.to_bytes())
+                    .map_err(|e| ReplError::File(path.display().to_string(), e.to_string()))?;
+            }
+            ReplCmd::Reset => {
+                self.vm = VM::new(vec![]);
+                self.session = Session::new();
+            }
+            ReplCmd::Step if at_end => {
+                println!("Nothing left to run");
+            }
+            ReplCmd::Step => {
+                self.step()?;
+            }
+            ReplCmd::Run if at_end => {
+                println!("Nothing left to run");
+            }
+            ReplCmd::Run => {
+                self.run_program()?;
+            }
+            ReplCmd::Debug => {
+                let mut debugger = Debugger::new(&mut self.vm);
+                for (name, &addr) in self.session.labels() {
+                    debugger.add_label(name, addr);
+                }
+                let result = debugger.interact(&self.interface);
+                self.interface.set_prompt(PROMPT)?;
+                result?;
             }
             ReplCmd::Quit => {
                 std::process::exit(0);
             }
         }
+        Ok(())
     }
 }
 
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
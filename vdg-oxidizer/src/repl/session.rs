@@ -0,0 +1,133 @@
+//! Everything typed into the REPL so far, assembled as one program.
+//!
+//! Each unit of input is assembled after all of the units before it, so
+//! labels, constants and macros defined earlier can be used by later
+//! lines just as they could further down a file. Only the code that the
+//! new unit adds is handed back to be appended to the VM's program.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::assembler::{AsmParseErr, Assembler, Parser, Preprocessor};
+use crate::assembler::parser::Parsed;
+use crate::repl::lexer::{ParseResult, ReplError};
+
+#[derive(Debug, Clone)]
+pub struct Session {
+    preprocessor: Preprocessor,
+    program: Vec<Parsed>,
+    code_len: usize,
+    labels: HashMap<String, usize>,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Self {
+            preprocessor: Preprocessor::new(),
+            program: Vec::new(),
+            code_len: 0,
+            labels: HashMap::new(),
+        }
+    }
+
+    /// Assembles a unit of source after everything before it and returns
+    /// the code it adds. If it fails, the session is left unchanged.
+    pub fn assemble(&mut self, source: &str) -> ParseResult<Vec<u8>> {
+        self.assemble_file(Path::new(""), source)
+    }
+
+    /// Assembles the source of the file at `path` like `assemble`,
+    /// resolving its includes relative to it.
+    pub fn assemble_file(&mut self, path: &Path, source: &str) -> ParseResult<Vec<u8>> {
+        // errors refer to lines within the unit, since it is lexed on its own
+        let mut preprocessor = self.preprocessor.clone();
+        let mut program = self.program.clone();
+        let result = preprocessor.process(source, path).and_then(|tokens| {
+            program.extend(Parser::new().parse(tokens)?);
+            let mut assembler = Assembler::new();
+            assembler.assemble(program.clone())?;
+            Ok(assembler)
+        });
+        let assembler = result.map_err(|err| match preprocessor.files().get(err.context().file) {
+            Some(file) if !file.as_os_str().is_empty() => {
+                AsmParseErr::InFile(file.display().to_string(), Box::new(err))
+            }
+            _ => err,
+        })?;
+        let object = assembler.to_object();
+        if !object.data().is_empty() {
+            return Err(ReplError::DataSection)
+        }
+
+        let code = object.code()[self.code_len..].to_vec();
+        self.preprocessor = preprocessor;
+        self.program = program;
+        self.code_len = object.code().len();
+        self.labels = assembler.symbols()
+            .keys()
+            .filter_map(|name| Some((name.clone(), assembler.address_of(name)?)))
+            .collect();
+        Ok(code)
+    }
+
+    /// The address of every label defined so far.
+    pub fn labels(&self) -> &HashMap<String, usize> {
+        &self.labels
+    }
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::{Opcode, VM};
+
+    #[test]
+    fn test_session() {
+        let mut session = Session::new();
+        assert_eq!(session.assemble("; nothing here").unwrap(), Vec::<u8>::new());
+        assert_eq!(
+            session.assemble("add $1 2 $1 /* inc */").unwrap(),
+            Assembler::new().assemble_source("add $1 2 $1").unwrap()
+        );
+
+        let mut session = Session::new();
+        let mut test_vm = VM::new(session.assemble("mov $1 0\n.equ LIMIT 5").unwrap());
+        test_vm.add_bytes(session.assemble("count: {\nadd $1 1 $1\nlt $1 LIMIT\njeq @count\n}").unwrap());
+        test_vm.add_bytes(session.assemble("jmp @done\nmov $1 100\ndone: { hlt }").unwrap());
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.test_register(1).unwrap(), 5);
+        assert_eq!(session.assemble("jmp @count").unwrap().len(), 6);
+        assert_eq!(session.labels()["count"], 8);
+    }
+
+    #[test]
+    fn test_session_errors() {
+        let mut session = Session::new();
+        session.assemble(".macro bump reg\nadd reg 1 reg\n.endm\nstart: { bump $1 }").unwrap();
+
+        let err = session.assemble("hlt\ncmp $1").unwrap_err();
+        assert!(matches!(err, ReplError::Asm(AsmParseErr::IncorrectOperandNo(2, 1, con)) if con.line == 2));
+        assert!(matches!(session.assemble("start: { hlt }"), Err(ReplError::Asm(AsmParseErr::DuplicateLabel(..)))));
+        assert!(matches!(session.assemble(".data\n.word 5"), Err(ReplError::DataSection)));
+
+        // a failed unit leaves nothing behind
+        assert!(session.assemble(".equ X 1\njmp @missing").is_err());
+        assert!(session.assemble(".equ X 2\nbump $2").is_ok());
+
+        // opcodes the VM can't run yet are reported, and the session goes on
+        let err = session.assemble("prt $1").unwrap_err();
+        assert!(matches!(err, ReplError::Asm(AsmParseErr::UnsupportedOpcode(Opcode::Prt, _))));
+        let mut test_vm = VM::new(session.assemble("start2: { aloc 8\nbump $1 }").unwrap());
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.memory().heap_size(), 8);
+
+        let err = session.assemble_file(Path::new("prog.vasm"), ".include \"missing.vasm\"").unwrap_err();
+        assert!(matches!(err, ReplError::Asm(AsmParseErr::InFile(file, _)) if file == "prog.vasm"));
+    }
+}
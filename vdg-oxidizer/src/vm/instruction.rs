@@ -1,6 +1,22 @@
+use std::fmt;
+
 use byteorder::*;
 
-use crate::assembler::Operand;
+use crate::assembler::{Address, Operand};
+use crate::vm::vm::VMError;
+
+/// Flag byte for a literal operand, followed by an i32.
+pub const LIT_FLAG: u8 = 0;
+/// Flag byte for a pointer operand, followed by an i64.
+pub const PTR_FLAG: u8 = 1;
+/// Flag byte for a register operand, followed by the register number.
+pub const REG_FLAG: u8 = 2;
+/// Flag byte for a register-relative pointer, followed by the base register,
+/// the index register, the scale and an i32 displacement.
+pub const IND_FLAG: u8 = 3;
+/// Stands in for the base or index register of an indexed pointer
+/// when it is not used.
+pub const NO_REG: u8 = 0xff;
 
 #[non_exhaustive]
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -39,7 +55,7 @@ pub enum Opcode {
     Wrt,  // Write to a file desc
 
     //* Numerical and bitwise operations
-    Inc,  // Increment
+    Inc = 0x20,  // Increment
     Dec,  // Decrement
     Add,  // Add
     Sub,  // Subtract
@@ -104,6 +120,41 @@ impl From<u8> for Opcode {
     }
 }
 
+impl Opcode {
+    /// Returns how each of the opcode's operand slots is laid out in bytecode.
+    ///
+    /// This is the single source of truth for instruction encoding;
+    /// the assembler, the VM and the disassembler all follow it.
+    pub fn layout(&self) -> [Slot; 3] {
+        use Opcode::*;
+        use Slot::*;
+
+        match self {
+            Hlt | Ret | Igl => [Empty, Empty, Empty],
+            Mov | Wrt => [Flagged, Flagged, Empty],
+            Jmp | Jmpf | Jmpb | Jeq | Jne |
+            Aloc | Dalc | Push | Call |
+            Prt | Open | Clse | Read => [Flagged, Empty, Empty],
+            Cmp | Lt | Gt | Le | Ge => [Flagged, Flagged, Empty],
+            Pop => [Register, Empty, Empty],
+            Inc | Dec | Not => [Flagged, Register, Empty],
+            Add | Sub | Mul | Div |
+            And | Or | Xor | Bsl | Bsr => [Flagged, Flagged, Register],
+        }
+    }
+}
+
+/// How a single operand slot is laid out in bytecode.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Slot {
+    /// The slot is unused.
+    Empty,
+    /// A flag byte followed by a literal, pointer or register.
+    Flagged,
+    /// A bare register byte, used where only a register is allowed.
+    Register,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Instruction {
     pub inst: Opcode,
@@ -138,13 +189,174 @@ impl Instruction {
         }
     }
 
+    /// Encodes the instruction into bytecode.
+    ///
+    /// Labels and pointer expressions must already have been resolved
+    /// by the assembler.
     pub fn to_bytes(self) -> Vec<u8> {
-        let mut bytes = Vec::<u8>::new();
-        bytes.push(self.inst as u8);
-        unimplemented!()
+        let mut bytes = vec![self.inst as u8];
+        let layout = self.inst.layout();
+        for (slot, op) in layout.iter().zip(vec![self.op1, self.op2, self.op3]) {
+            match slot {
+                Slot::Flagged => encode_operand(op, &mut bytes),
+                Slot::Register => encode_register(op, &mut bytes),
+                Slot::Empty => {}
+            }
+        }
+
+        bytes
+    }
+
+    /// Returns where each operand's literal, pointer or displacement
+    /// starts in the encoded instruction, along with its size.
+    /// Registers have no such field.
+    pub fn value_fields(&self) -> [Option<(usize, usize)>; 3] {
+        let mut fields = [None, None, None];
+        let mut pos = 1;
+        let ops = [&self.op1, &self.op2, &self.op3];
+        for (i, slot) in self.inst.layout().iter().enumerate() {
+            match (slot, ops[i]) {
+                (Slot::Flagged, Some(Operand::NumLiteral(_))) => {
+                    fields[i] = Some((pos + 1, 4));
+                    pos += 5;
+                }
+                (Slot::Flagged, Some(Operand::Pointer(_))) => {
+                    fields[i] = Some((pos + 1, 8));
+                    pos += 9;
+                }
+                (Slot::Flagged, Some(Operand::Address(_))) => {
+                    fields[i] = Some((pos + 4, 4));
+                    pos += 8;
+                }
+                (Slot::Flagged, _) => pos += 2,
+                (Slot::Register, _) => pos += 1,
+                (Slot::Empty, _) => {}
+            }
+        }
+        fields
+    }
+
+    /// Decodes a single instruction from the start of a byte buffer.
+    ///
+    /// Returns the instruction along with the number of bytes it took up.
+    pub fn decode(bytes: &[u8]) -> Result<(Instruction, usize), VMError> {
+        let opcode = Opcode::from(*bytes.first().ok_or(VMError::SegFault)?);
+        if opcode == Opcode::Igl {
+            return Err(VMError::IglOpcode)
+        }
+        let mut pos = 1;
+        let mut ops = [None, None, None];
+        for (i, slot) in opcode.layout().iter().enumerate() {
+            ops[i] = match slot {
+                Slot::Flagged => Some(decode_operand(bytes, &mut pos)?),
+                Slot::Register => {
+                    let reg = decode_register(bytes, &mut pos)?;
+                    Some(Operand::Register(reg))
+                }
+                Slot::Empty => None,
+            };
+        }
+        let [op1, op2, op3] = ops;
+
+        Ok((Instruction::from_parsed(opcode, (op1, op2, op3)), pos))
     }
 }
 
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", format!("{:?}", self.inst).to_lowercase())?;
+        for op in [&self.op1, &self.op2, &self.op3].iter().copied().flatten() {
+            write!(f, " {}", op)?;
+        }
+        Ok(())
+    }
+}
+
+fn encode_operand(op: Option<Operand>, bytes: &mut Vec<u8>) {
+    match op {
+        Some(Operand::NumLiteral(num)) => {
+            bytes.push(LIT_FLAG);
+            bytes.extend_from_slice(&i32_to_bytes(num));
+        }
+        Some(Operand::Pointer(ptr)) => {
+            let addr = ptr.trim().parse::<i64>()
+                .expect("pointer operands should be resolved before encoding");
+            bytes.push(PTR_FLAG);
+            bytes.extend_from_slice(&i64_to_bytes(addr));
+        }
+        Some(Operand::Address(addr)) => {
+            bytes.push(IND_FLAG);
+            bytes.push(addr.base.unwrap_or(NO_REG));
+            bytes.push(addr.index.unwrap_or(NO_REG));
+            bytes.push(addr.scale);
+            bytes.extend_from_slice(&i32_to_bytes(addr.disp));
+        }
+        Some(Operand::Register(reg)) => {
+            bytes.push(REG_FLAG);
+            bytes.push(reg);
+        }
+        Some(Operand::LabelUse(name)) => {
+            panic!("label @{} should be resolved before encoding", name)
+        }
+        Some(Operand::Expr(expr)) => {
+            panic!("expression ({}) should be folded before encoding", expr)
+        }
+        None => panic!("missing operand"),
+    }
+}
+
+fn encode_register(op: Option<Operand>, bytes: &mut Vec<u8>) {
+    match op {
+        Some(Operand::Register(reg)) => bytes.push(reg),
+        Some(op) => panic!("expected a register operand, found {}", op),
+        None => panic!("missing operand"),
+    }
+}
+
+fn decode_operand(bytes: &[u8], pos: &mut usize) -> Result<Operand, VMError> {
+    let flag = *bytes.get(*pos).ok_or(VMError::SegFault)?;
+    *pos += 1;
+    match flag {
+        LIT_FLAG => {
+            let buf = bytes.get(*pos..*pos + 4).ok_or(VMError::SegFault)?;
+            *pos += 4;
+            Ok(Operand::NumLiteral(LittleEndian::read_i32(buf)))
+        }
+        PTR_FLAG => {
+            let buf = bytes.get(*pos..*pos + 8).ok_or(VMError::SegFault)?;
+            *pos += 8;
+            Ok(Operand::Pointer(LittleEndian::read_i64(buf).to_string()))
+        }
+        REG_FLAG => Ok(Operand::Register(decode_register(bytes, pos)?)),
+        IND_FLAG => {
+            let buf = bytes.get(*pos..*pos + 7).ok_or(VMError::SegFault)?;
+            let reg = |byte: u8| match byte {
+                NO_REG => Ok(None),
+                0..=31 => Ok(Some(byte)),
+                _ => Err(VMError::OpcodeErr),
+            };
+            let addr = Address {
+                base: reg(buf[0])?,
+                index: reg(buf[1])?,
+                scale: buf[2],
+                disp: LittleEndian::read_i32(&buf[3..]),
+            };
+            *pos += 7;
+            Ok(Operand::Address(addr))
+        }
+        _ => Err(VMError::OpcodeErr),
+    }
+}
+
+fn decode_register(bytes: &[u8], pos: &mut usize) -> Result<u8, VMError> {
+    let reg = *bytes.get(*pos).ok_or(VMError::SegFault)?;
+    *pos += 1;
+    if reg > 31 {
+        return Err(VMError::OpcodeErr)
+    }
+    Ok(reg)
+}
+
 fn i32_to_bytes(num: i32) -> [u8; 4] {
     let mut buf: [u8; 4] = [0, 0, 0, 0];
     buf.as_mut().write_i32::<LittleEndian>(num).unwrap();
@@ -152,6 +364,13 @@ fn i32_to_bytes(num: i32) -> [u8; 4] {
     buf
 }
 
+fn i64_to_bytes(num: i64) -> [u8; 8] {
+    let mut buf: [u8; 8] = [0; 8];
+    buf.as_mut().write_i64::<LittleEndian>(num).unwrap();
+
+    buf
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -168,5 +387,116 @@ mod tests {
         assert_eq!(movopcode, Opcode::Mov);
         assert_eq!(iglopcode, Opcode::Igl);
     }
+
+    /// Builds an instruction with sample operands that fit the opcode's layout.
+    fn sample_instruction(opcode: Opcode) -> Instruction {
+        let samples = [
+            Operand::NumLiteral(-500),
+            Operand::Pointer(String::from("4294967296")),
+            Operand::Register(31),
+        ];
+        let mut ops = vec![];
+        for (i, slot) in opcode.layout().iter().enumerate() {
+            ops.push(match slot {
+                Slot::Flagged => Some(samples[i].clone()),
+                Slot::Register => Some(Operand::Register(i as u8)),
+                Slot::Empty => None,
+            });
+        }
+        Instruction::from_parsed(opcode, (ops[0].clone(), ops[1].clone(), ops[2].clone()))
+    }
+
+    #[test]
+    fn test_round_trip_every_opcode() {
+        for byte in 0x00..=0xff {
+            let opcode = Opcode::from(byte);
+            if opcode == Opcode::Igl {
+                continue
+            }
+            let inst = sample_instruction(opcode);
+            let mut bytes = inst.clone().to_bytes();
+            let len = bytes.len();
+            bytes.extend(vec![0xff, 0xff]);
+
+            assert_eq!(Instruction::decode(&bytes), Ok((inst, len)), "opcode {:?}", opcode);
+        }
+    }
+
+    #[test]
+    fn test_operand_encoding() {
+        let inst = Instruction::from_parsed(
+            Opcode::Add,
+            (
+                Some(Operand::Register(3)),
+                Some(Operand::NumLiteral(1)),
+                Some(Operand::Register(4)),
+            )
+        );
+        assert_eq!(inst.to_bytes(), vec![0x22, 0x02, 0x03, 0x00, 0x01, 0x00, 0x00, 0x00, 0x04]);
+
+        let inst = Instruction::from_parsed(
+            Opcode::Jmp, (Some(Operand::Pointer(String::from("-1"))), None, None)
+        );
+        assert_eq!(inst.to_bytes(), vec![0x02, 0x01, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+
+        let addr = Address { base: Some(1), index: Some(2), scale: 4, disp: 8 };
+        let inst = Instruction::from_parsed(
+            Opcode::Mov, (Some(Operand::Address(addr)), Some(Operand::Register(3)), None)
+        );
+        let bytes = inst.clone().to_bytes();
+        assert_eq!(bytes, vec![0x01, 0x03, 0x01, 0x02, 0x04, 0x08, 0x00, 0x00, 0x00, 0x02, 0x03]);
+        assert_eq!(inst.value_fields(), [Some((5, 4)), None, None]);
+        assert_eq!(Instruction::decode(&bytes), Ok((inst, 11)));
+    }
+
+    #[test]
+    fn test_value_fields() {
+        let inst = Instruction::from_parsed(
+            Opcode::Mov, (Some(Operand::Register(1)), Some(Operand::Pointer(String::from("16"))), None)
+        );
+        assert_eq!(inst.value_fields(), [None, Some((4, 8)), None]);
+
+        let inst = Instruction::from_parsed(
+            Opcode::Add,
+            (Some(Operand::NumLiteral(1)), Some(Operand::NumLiteral(2)), Some(Operand::Register(4)))
+        );
+        assert_eq!(inst.value_fields(), [Some((2, 4)), Some((7, 4)), None]);
+    }
+
+    #[test]
+    fn test_instruction_display() {
+        let inst = Instruction::from_parsed(
+            Opcode::Mov,
+            (Some(Operand::Register(2)), Some(Operand::NumLiteral(500)), None)
+        );
+        assert_eq!(inst.to_string(), "mov $2 500");
+
+        let inst = Instruction::from_parsed(Opcode::Hlt, (None, None, None));
+        assert_eq!(inst.to_string(), "hlt");
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        assert_eq!(Instruction::decode(&[]), Err(VMError::SegFault));
+        assert_eq!(Instruction::decode(&[0xf7]), Err(VMError::IglOpcode));
+        // jmp with a truncated literal
+        assert_eq!(Instruction::decode(&[0x02, 0x00, 0x01]), Err(VMError::SegFault));
+        // jmp with an unknown flag
+        assert_eq!(Instruction::decode(&[0x02, 0x07, 0x01]), Err(VMError::OpcodeErr));
+        // jmp [$40]
+        assert_eq!(Instruction::decode(&[0x02, 0x03, 0x28, 0xff, 0x01, 0, 0, 0, 0]), Err(VMError::OpcodeErr));
+        // pop $32
+        assert_eq!(Instruction::decode(&[0x0f, 0x20]), Err(VMError::OpcodeErr));
+    }
+
+    #[test]
+    fn test_opcode_as_u8() {
+        for byte in 0x00..0x2c {
+            let opcode = Opcode::from(byte);
+            if opcode != Opcode::Igl {
+                assert_eq!(opcode as u8, byte);
+            }
+        }
+    }
 }
 
@@ -0,0 +1,234 @@
+//! A record of what each instruction changed, so that the VM can run
+//! backwards.
+//!
+//! While a journal is enabled, the VM adds an entry for every instruction
+//! it runs, holding the program counter, flags and memory sizes from
+//! before the instruction, any part of the stack it could throw away, and
+//! the old value of every register and word of memory it wrote. Undoing an entry puts all of them back. Only the most recent
+//! entries are kept, up to the journal's limit.
+
+use std::collections::VecDeque;
+
+use crate::vm::vm::MemoryState;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Journal {
+    entries: VecDeque<Entry>,
+    limit: usize,
+    /// Set while an instruction is running, so that only its writes
+    /// are added to the newest entry.
+    open: bool,
+}
+
+/// The state an instruction changed, from just before it ran.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub(crate) pc: usize,
+    pub(crate) eq: bool,
+    pub(crate) remainder: i32,
+    pub(crate) registers: Vec<(usize, i32)>,
+    pub(crate) memory: Vec<(i64, i32)>,
+    pub(crate) stack: MemoryState,
+}
+
+impl Journal {
+    /// Creates a journal that keeps at most `limit` instructions.
+    pub fn new(limit: usize) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            limit,
+            open: false,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Changes how many instructions are kept, forgetting the oldest ones
+    /// if there are now too many.
+    pub fn set_limit(&mut self, limit: usize) {
+        let excess = self.entries.len().saturating_sub(limit);
+        self.entries.drain(..excess);
+        self.limit = limit;
+    }
+
+    /// The recorded instructions, oldest first.
+    pub fn entries(&self) -> impl DoubleEndedIterator<Item = &Entry> {
+        self.entries.iter()
+    }
+
+    /// Returns the newest instruction that wrote to register `reg`, and
+    /// how many instructions have run since.
+    pub fn last_register_write(&self, reg: usize) -> Option<(&Entry, usize)> {
+        self.find_last(|entry| entry.registers.iter().any(|&(r, _)| r == reg))
+    }
+
+    /// Returns the newest instruction that wrote to any of the four bytes
+    /// at `addr`, and how many instructions have run since.
+    pub fn last_memory_write(&self, addr: i64) -> Option<(&Entry, usize)> {
+        self.find_last(|entry| entry.memory.iter().any(|&(a, _)| (a - addr).abs() < 4))
+    }
+
+    fn find_last<F: Fn(&Entry) -> bool>(&self, wrote: F) -> Option<(&Entry, usize)> {
+        self.entries.iter()
+            .rev()
+            .enumerate()
+            .find(|(_, entry)| wrote(entry))
+            .map(|(ago, entry)| (entry, ago))
+    }
+
+    /// Starts the entry for an instruction about to run.
+    pub(crate) fn begin(&mut self, entry: Entry) {
+        if self.limit == 0 {
+            return
+        }
+        if self.entries.len() == self.limit {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+        self.open = true;
+    }
+
+    pub(crate) fn end(&mut self) {
+        self.open = false;
+    }
+
+    /// The entry for the instruction that is running, if there is one.
+    pub(crate) fn current(&mut self) -> Option<&mut Entry> {
+        if !self.open {
+            return None
+        }
+        self.entries.back_mut()
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<Entry> {
+        self.entries.pop_back()
+    }
+}
+
+impl Entry {
+    /// The address of the instruction.
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+    use crate::vm::{Opcode, VM};
+    use crate::vm::instruction::LIT_FLAG;
+
+    fn vm_for(source: &str, limit: usize) -> VM {
+        let mut vm = VM::new(Assembler::new().assemble_source(source).unwrap());
+        vm.enable_journal(limit);
+        vm
+    }
+
+    #[test]
+    fn test_step_back_to_start() {
+        let mut test_vm = vm_for("\
+            mov $2 @cell\n\
+            mov [$2] 99\n\
+            mov $1 3\n\
+            call @fact\n\
+            mov [$2] $0\n\
+            hlt\n\
+            fact: { le $1 1\n\
+            jeq @base\n\
+            push $1\n\
+            dec $1 $1\n\
+            call @fact\n\
+            pop $1\n\
+            mul $0 $1 $0\n\
+            div $0 4 $3\n\
+            ret }\n\
+            base: { mov $0 1\n\
+            ret }\n\
+            .data\n\
+            cell: { .word 0 }", 1000);
+        let start = test_vm.clone();
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.test_register(0).unwrap(), 6);
+        assert_eq!(test_vm.load(test_vm.test_register(2).unwrap() as i64).unwrap(), 6);
+
+        let steps = test_vm.journal().unwrap().len();
+        for _ in 0..steps {
+            assert!(test_vm.step_back().unwrap());
+        }
+        assert!(!test_vm.step_back().unwrap());
+        assert_eq!(test_vm, start);
+    }
+
+    #[test]
+    fn test_step_back_in_frame() {
+        let mut test_vm = vm_for("push 5\ncall @f\nhlt\nf: { push 6\npush 7\nret }", 100);
+        while Opcode::from(test_vm.program()[test_vm.pc()]) != Opcode::Ret {
+            test_vm.run_once().unwrap();
+        }
+        // undo the ret, which threw away the whole frame
+        let inside = test_vm.clone();
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.memory().stack_size(), 4);
+        assert!(test_vm.step_back().unwrap());
+        assert_eq!(test_vm.memory(), inside.memory());
+        assert_eq!(test_vm.pc(), inside.pc());
+    }
+
+    #[test]
+    fn test_step_back_allocation() {
+        // aloc 8, hlt
+        let mut code = vec![Opcode::Aloc as u8, LIT_FLAG];
+        code.extend_from_slice(&8i32.to_le_bytes());
+        code.push(Opcode::Hlt as u8);
+        let mut test_vm = VM::new(code);
+        test_vm.enable_journal(10);
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.memory().heap_size(), 8);
+        assert!(test_vm.step_back().unwrap() && test_vm.step_back().unwrap());
+        assert_eq!(test_vm.memory().heap_size(), 0);
+        assert!(test_vm.memory().heap_bytes().is_empty());
+    }
+
+    #[test]
+    fn test_journal_limit_and_last_writes() {
+        let mut test_vm = vm_for("\
+            mov $5 1\n\
+            mov $6 2\n\
+            mov [@cell] $5\n\
+            add $6 1 $6\n\
+            hlt\n\
+            .data\n\
+            cell: { .word 0 }", 3);
+        test_vm.run().unwrap();
+
+        let journal = test_vm.journal().unwrap();
+        assert_eq!(journal.len(), 3);
+        assert_eq!(journal.entries().next().unwrap().pc(), 16);
+        let (entry, ago) = journal.last_register_write(6).unwrap();
+        assert_eq!((entry.pc(), ago), (28, 1));
+        // the write to $5 has fallen out of the history
+        assert!(journal.last_register_write(5).is_none());
+        let mut shorter = journal.clone();
+        shorter.set_limit(1);
+        assert_eq!(shorter.entries().map(Entry::pc).collect::<Vec<_>>(), vec![37]);
+        let cell = test_vm.program().len() as i64 - 4;
+        assert_eq!(journal.last_memory_write(cell + 2).map(|(entry, ago)| (entry.pc(), ago)), Some((16, 2)));
+
+        assert!(test_vm.step_back().unwrap() && test_vm.step_back().unwrap() && test_vm.step_back().unwrap());
+        assert!(!test_vm.step_back().unwrap());
+        assert_eq!(test_vm.pc(), 16);
+        assert_eq!(test_vm.load(cell).unwrap(), 0);
+        assert_eq!(test_vm.test_register(5).unwrap(), 1);
+    }
+}
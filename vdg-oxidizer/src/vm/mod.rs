@@ -1,5 +1,8 @@
+#[allow(clippy::module_inception)]
 pub mod vm;
 pub mod instruction;
+pub mod journal;
+pub mod trace;
 
 pub use self::vm::VM;
 pub use self::instruction::{
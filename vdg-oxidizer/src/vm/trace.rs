@@ -0,0 +1,305 @@
+//! A log of every instruction the VM runs.
+//!
+//! While a tracer is attached, each instruction is written out as it runs,
+//! with its address, the opcode and operands it was decoded into, and the
+//! registers and flags it changed. Traces can be read as plain text, or
+//! written as JSON lines to be compared or searched by other tools:
+//!
+//! ```text
+//! 0x0000  mov $1 4                      $1: 0 -> 4
+//! 0x0008  lt $1 5                       eq: false -> true
+//! ```
+//!
+//! ```text
+//! {"pc":0,"opcode":"mov","operands":["$1","4"],"changes":{"$1":[0,4]}}
+//! {"pc":8,"opcode":"lt","operands":["$1","5"],"changes":{"eq":[false,true]}}
+//! ```
+
+use std::cell::RefCell;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::vm::{Instruction, VM};
+use crate::vm::vm::VMError;
+
+/// How wide the instruction column of a text trace is.
+const INSTRUCTION_WIDTH: usize = 28;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceFormat {
+    Text,
+    Json,
+}
+
+/// Writes a record of every instruction to a log.
+///
+/// A cloned tracer writes to the same log, so a VM and its clones share
+/// one trace.
+#[derive(Clone)]
+pub struct Tracer {
+    out: Rc<RefCell<dyn Write>>,
+    format: TraceFormat,
+}
+
+impl Tracer {
+    pub fn new<W: Write + 'static>(out: W, format: TraceFormat) -> Self {
+        Self {
+            out: Rc::new(RefCell::new(out)),
+            format,
+        }
+    }
+
+    /// Creates a tracer that writes to a new file at `path`.
+    pub fn create<P: AsRef<Path>>(path: P, format: TraceFormat) -> io::Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?), format))
+    }
+
+    pub fn format(&self) -> TraceFormat {
+        self.format
+    }
+
+    pub fn write(&self, record: &Record) -> io::Result<()> {
+        let mut out = self.out.borrow_mut();
+        match self.format {
+            TraceFormat::Text => writeln!(out, "{}", record),
+            TraceFormat::Json => writeln!(out, "{}", record.to_json()),
+        }
+    }
+
+    pub fn flush(&self) -> io::Result<()> {
+        self.out.borrow_mut().flush()
+    }
+}
+
+impl fmt::Debug for Tracer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Tracer").field("format", &self.format).finish()
+    }
+}
+
+impl PartialEq for Tracer {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.out, &other.out) && self.format == other.format
+    }
+}
+
+/// What a single instruction did.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub pc: usize,
+    /// The instruction at `pc`, or `None` if it ran off the end of the
+    /// program. An illegal opcode is decoded with no operands.
+    pub instruction: Option<Instruction>,
+    /// Each register that changed, with its old and new values.
+    pub registers: Vec<(usize, i32, i32)>,
+    pub eq: Option<(bool, bool)>,
+    pub remainder: Option<(i32, i32)>,
+    /// The error the instruction stopped the VM with, if any.
+    pub error: Option<VMError>,
+}
+
+impl Record {
+    /// Describes every change as a name and an old and new value.
+    fn changes(&self) -> Vec<(String, String, String)> {
+        let mut changes: Vec<_> = self.registers.iter()
+            .map(|(reg, old, new)| (format!("${}", reg), old.to_string(), new.to_string()))
+            .collect();
+        if let Some((old, new)) = self.eq {
+            changes.push((String::from("eq"), old.to_string(), new.to_string()));
+        }
+        if let Some((old, new)) = self.remainder {
+            changes.push((String::from("rem"), old.to_string(), new.to_string()));
+        }
+        changes
+    }
+
+    /// Formats the record as a single line of JSON.
+    pub fn to_json(&self) -> String {
+        let (opcode, operands) = match &self.instruction {
+            Some(inst) => {
+                let operands: Vec<String> = [&inst.op1, &inst.op2, &inst.op3].iter()
+                    .copied()
+                    .flatten()
+                    .map(|op| json_string(&op.to_string()))
+                    .collect();
+                (json_string(&opcode_name(inst)), operands)
+            }
+            None => (String::from("null"), Vec::new()),
+        };
+        let changes: Vec<String> = self.changes()
+            .iter()
+            .map(|(name, old, new)| format!("{}:[{},{}]", json_string(name), old, new))
+            .collect();
+        let mut json = format!(
+            "{{\"pc\":{},\"opcode\":{},\"operands\":[{}],\"changes\":{{{}}}",
+            self.pc, opcode, operands.join(","), changes.join(",")
+        );
+        if let Some(err) = self.error {
+            json.push_str(&format!(",\"error\":{}", json_string(&err.to_string())));
+        }
+        json.push('}');
+        json
+    }
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let inst = match &self.instruction {
+            Some(inst) => inst.to_string(),
+            None => String::from("??"),
+        };
+        let changes: Vec<String> = self.changes()
+            .iter()
+            .map(|(name, old, new)| format!("{}: {} -> {}", name, old, new))
+            .collect();
+        let line = format!("{:#06x}  {:<width$}  {}", self.pc, inst, changes.join(", "), width = INSTRUCTION_WIDTH);
+        write!(f, "{}", line.trim_end())?;
+        if let Some(err) = self.error {
+            write!(f, "\n        ! {}", err)?;
+        }
+        Ok(())
+    }
+}
+
+/// The state an instruction starts from, kept until it has run so that
+/// its record can say what changed.
+pub(crate) struct Pending {
+    pc: usize,
+    instruction: Option<Instruction>,
+    registers: [i32; 32],
+    eq: bool,
+    remainder: i32,
+}
+
+impl Pending {
+    /// Decodes the instruction at the program counter, using the same
+    /// layout the VM reads its operands with.
+    pub(crate) fn new(vm: &VM) -> Self {
+        let pc = vm.pc();
+        let program = vm.program();
+        let instruction = match Instruction::decode(program.get(pc..).unwrap_or(&[])) {
+            Ok((inst, _)) => Some(inst),
+            Err(_) => program.get(pc).map(|&byte| Instruction::new(byte)),
+        };
+        Self {
+            pc,
+            instruction,
+            registers: *vm.registers(),
+            eq: vm.eq_flag(),
+            remainder: vm.remainder(),
+        }
+    }
+
+    pub(crate) fn finish(self, vm: &VM, error: Option<VMError>) -> Record {
+        let registers = self.registers.iter()
+            .zip(vm.registers().iter())
+            .enumerate()
+            .filter(|(_, (old, new))| old != new)
+            .map(|(reg, (&old, &new))| (reg, old, new))
+            .collect();
+        Record {
+            pc: self.pc,
+            instruction: self.instruction,
+            registers,
+            eq: Some((self.eq, vm.eq_flag())).filter(|(old, new)| old != new),
+            remainder: Some((self.remainder, vm.remainder())).filter(|(old, new)| old != new),
+            error,
+        }
+    }
+}
+
+fn opcode_name(inst: &Instruction) -> String {
+    format!("{:?}", inst.inst).to_lowercase()
+}
+
+fn json_string(s: &str) -> String {
+    let mut json = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            c if c.is_control() => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+
+    /// A log that can still be read after the tracer has taken it.
+    #[derive(Clone, Default)]
+    struct SharedLog(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedLog {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedLog {
+        fn lines(&self) -> Vec<String> {
+            String::from_utf8(self.0.borrow().clone()).unwrap()
+                .lines()
+                .map(String::from)
+                .collect()
+        }
+    }
+
+    fn trace(source: &str, format: TraceFormat) -> (Result<(), VMError>, Vec<String>) {
+        let log = SharedLog::default();
+        let mut test_vm = VM::new(Assembler::new().assemble_source(source).unwrap());
+        test_vm.enable_trace(Tracer::new(log.clone(), format));
+        let result = test_vm.run();
+        (result, log.lines())
+    }
+
+    #[test]
+    fn test_text_trace() {
+        let (result, lines) = trace("mov $1 4\nlt $1 5\ndiv 7 2 $2\nhlt", TraceFormat::Text);
+        result.unwrap();
+        assert_eq!(lines, vec![
+            "0x0000  mov $1 4                      $1: 0 -> 4",
+            "0x0008  lt $1 5                       eq: false -> true",
+            "0x0010  div 7 2 $2                    $2: 0 -> 3, rem: 0 -> 1",
+            "0x001c  hlt",
+        ]);
+    }
+
+    #[test]
+    fn test_json_trace() {
+        let (result, lines) = trace("mov [@cell] 3\nmov $3 [@cell]\ndiv $3 0 $4\n.data\ncell: { .word 0 }", TraceFormat::Json);
+        assert_eq!(result, Err(VMError::DivByZero));
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("{\"pc\":0,\"opcode\":\"mov\",\"operands\":[\"["));
+        assert!(lines[0].ends_with("\"3\"],\"changes\":{}}"));
+        assert!(lines[1].ends_with("\"changes\":{\"$3\":[0,3]}}"));
+        assert_eq!(
+            lines[2],
+            "{\"pc\":27,\"opcode\":\"div\",\"operands\":[\"$3\",\"0\",\"$4\"],\"changes\":{},\
+             \"error\":\"VM Error: attempted to divide by zero\"}"
+        );
+    }
+
+    #[test]
+    fn test_illegal_opcode_record() {
+        let log = SharedLog::default();
+        let mut test_vm = VM::new(vec![0xf7]);
+        test_vm.enable_trace(Tracer::new(log.clone(), TraceFormat::Text));
+        assert_eq!(test_vm.run_once(), Err(VMError::IglOpcode));
+        assert_eq!(log.lines(), vec!["0x0000  igl", "        ! VM Error: illegal opcode encountered"]);
+        assert_eq!(json_string("say \"hi\"\n"), "\"say \\\"hi\\\"\\n\"");
+    }
+}
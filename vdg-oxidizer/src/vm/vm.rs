@@ -1,9 +1,12 @@
 use std::convert::TryInto;
 use byteorder::*;
 
-use crate::vm::instruction::Opcode;
+use crate::vm::instruction::{Opcode, LIT_FLAG, PTR_FLAG, REG_FLAG, IND_FLAG, NO_REG};
+use crate::vm::journal::{Entry, Journal};
+use crate::vm::trace::{Pending, Tracer};
+use crate::disassembler::Disassembler;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct VM {
     registers: [i32; 32],
     program: Vec<u8>,
@@ -11,6 +14,8 @@ pub struct VM {
     pc: usize,
     remainder: i32,
     eq: bool,
+    journal: Option<Journal>,
+    tracer: Option<Tracer>,
 }
 
 impl VM {
@@ -22,124 +27,299 @@ impl VM {
             pc: 0,
             remainder: 0,
             eq: false,
+            journal: None,
+            tracer: None,
         }
     }
 
     pub fn run(&mut self) -> Result<(), VMError> {
-        while !self.execute()? {}
+        while !self.run_once()? {}
         Ok(())
     }
 
     pub fn run_once(&mut self) -> Result<bool, VMError> {
-        self.execute()
+        let pending = self.tracer.as_ref().map(|_| Pending::new(self));
+        if let Some(journal) = &mut self.journal {
+            let opcode = self.program.get(self.pc).map_or(Opcode::Igl, |&byte| Opcode::from(byte));
+            journal.begin(Entry {
+                pc: self.pc,
+                eq: self.eq,
+                remainder: self.remainder,
+                registers: Vec::new(),
+                memory: Vec::new(),
+                stack: self.memory.save_state(opcode),
+            });
+        }
+        let result = self.execute();
+        if let Some(journal) = &mut self.journal {
+            journal.end();
+        }
+        if let Some(pending) = pending {
+            let record = pending.finish(self, result.err());
+            let tracer = self.tracer.as_ref().unwrap();
+            tracer.write(&record).map_err(|_| VMError::TraceWrite)?;
+            if result != Ok(false) {
+                // the program has stopped, so the whole trace can be read
+                tracer.flush().map_err(|_| VMError::TraceWrite)?;
+            }
+        }
+        result
+    }
+
+    /// Starts writing a record of every instruction that runs to `tracer`.
+    pub fn enable_trace(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    /// Stops tracing, writing out anything the tracer has not yet.
+    pub fn disable_trace(&mut self) -> Result<(), VMError> {
+        match self.tracer.take() {
+            Some(tracer) => tracer.flush().map_err(|_| VMError::TraceWrite),
+            None => Ok(()),
+        }
+    }
+
+    pub fn tracer(&self) -> Option<&Tracer> {
+        self.tracer.as_ref()
+    }
+
+    /// Starts recording every instruction that runs, so that they can be
+    /// undone with `step_back`. At most `limit` instructions are kept.
+    pub fn enable_journal(&mut self, limit: usize) {
+        self.journal = Some(Journal::new(limit));
+    }
+
+    pub fn disable_journal(&mut self) {
+        self.journal = None;
+    }
+
+    pub fn journal(&self) -> Option<&Journal> {
+        self.journal.as_ref()
+    }
+
+    pub fn journal_mut(&mut self) -> Option<&mut Journal> {
+        self.journal.as_mut()
+    }
+
+    /// Undoes the last instruction in the journal, including one that
+    /// faulted partway through. Returns false if there is none to undo.
+    pub fn step_back(&mut self) -> Result<bool, VMError> {
+        let entry = match self.journal.as_mut().and_then(Journal::pop) {
+            Some(entry) => entry,
+            None => return Ok(false),
+        };
+        for &(reg, old) in entry.registers.iter().rev() {
+            self.registers[reg] = old;
+        }
+        // the stack and heap go back to their old sizes first, so that every
+        // word the instruction wrote is in bounds again
+        self.memory.restore(entry.stack);
+        for &(addr, old) in entry.memory.iter().rev() {
+            self.write_word(addr, old)?;
+        }
+        self.pc = entry.pc;
+        self.eq = entry.eq;
+        self.remainder = entry.remainder;
+        Ok(true)
     }
 
     #[inline]
-    #[allow(unreachable_code)]
     fn execute(&mut self) -> Result<bool, VMError> {
         if self.pc >= self.program.len() {
             return Err(VMError::SegFault)
         }
         match self.decode_opcode() {
             Opcode::Hlt => {
-                println!("Halting VM");
                 Ok(true)
             }
-            Opcode::Mov => { //todo: enable support for pointers
-                let register = self.next_8_bits() as usize;
-                let flag = self.next_8_bits() as usize;
-                let value = if flag == 0 {
-                    self.read_i32()
-                } else if flag == 1 {
-                    unimplemented!("pointer calling conventions not implemented");
-                    self.read_i64();
-                } else if flag == 2 {
-                    self.registers[self.next_8_bits() as usize]
-                } else {
-                    eprintln!("Error decoding opcode");
-                    return Err(VMError::OpcodeErr)
+            Opcode::Mov => {
+                let dest = match self.next_8_bits()? {
+                    REG_FLAG => Dest::Register(self.read_register()?),
+                    flag => Dest::Memory(self.read_address(flag)?),
                 };
-                self.registers[register] = value;
+                let value = match self.next_8_bits()? {
+                    LIT_FLAG => self.read_i32()?,
+                    REG_FLAG => self.registers[self.read_register()?],
+                    flag => {
+                        let addr = self.read_address(flag)?;
+                        self.load(addr)?
+                    }
+                };
+                match dest {
+                    Dest::Register(reg) => self.set_register(reg, value),
+                    Dest::Memory(addr) => self.store(addr, value)?,
+                }
 
                 Ok(false)
             }
             Opcode::Jmp => {
-                unimplemented!()
+                let target = self.read_operand()?;
+                self.jump_to(target)?;
+                Ok(false)
             }
             Opcode::Jmpf => {
-                unimplemented!()
+                let offset = self.read_operand()?;
+                self.jump_to(self.pc as i64 + offset)?;
+                Ok(false)
             }
             Opcode::Jmpb => {
-                unimplemented!()
+                let offset = self.read_operand()?;
+                self.jump_to(self.pc as i64 - offset)?;
+                Ok(false)
             }
             Opcode::Cmp => {
-                unimplemented!()
+                let (lhs, rhs) = (self.read_value()?, self.read_value()?);
+                self.eq = lhs == rhs;
+                Ok(false)
             }
             Opcode::Lt => {
-                unimplemented!()
+                let (lhs, rhs) = (self.read_value()?, self.read_value()?);
+                self.eq = lhs < rhs;
+                Ok(false)
             }
             Opcode::Gt => {
-                unimplemented!()
+                let (lhs, rhs) = (self.read_value()?, self.read_value()?);
+                self.eq = lhs > rhs;
+                Ok(false)
             }
             Opcode::Le => {
-                unimplemented!()
+                let (lhs, rhs) = (self.read_value()?, self.read_value()?);
+                self.eq = lhs <= rhs;
+                Ok(false)
             }
             Opcode::Ge => {
-                unimplemented!()
+                let (lhs, rhs) = (self.read_value()?, self.read_value()?);
+                self.eq = lhs >= rhs;
+                Ok(false)
             }
             Opcode::Jeq => {
-                unimplemented!()
+                let target = self.read_operand()?;
+                if self.eq {
+                    self.jump_to(target)?;
+                }
+                Ok(false)
             }
             Opcode::Jne => {
-                unimplemented!()
+                let target = self.read_operand()?;
+                if !self.eq {
+                    self.jump_to(target)?;
+                }
+                Ok(false)
             }
             Opcode::Aloc => {
-                let flag = self.next_8_bits();
-                let value = if flag == 0 {
-                    self.read_i32()
-                } else if flag == 1 {
-                    unimplemented!("pointer size not yet worked out");
-                    self.read_i64();
-                } else if flag == 2 {
-                    self.registers[self.next_8_bits() as usize]
-                } else {
-                    return Err(VMError::OpcodeErr)
-                };
-                self.memory.allocate_heap(value as usize);
+                let size = self.read_value()?;
+                if size < 0 {
+                    return Err(VMError::InvalidSize(size))
+                }
+                self.memory.allocate_heap(size as usize);
                 Ok(false)
             }
             Opcode::Push => {
-                unimplemented!("Calling conventions not yet specified")
+                let value = self.read_value()?;
+                self.memory.push(value)?;
+                Ok(false)
             }
             Opcode::Pop => {
-                unimplemented!("Calling conventions not yet specified")
+                let value = self.memory.pop()?;
+                self.write_register(value)?;
+                Ok(false)
             }
             Opcode::Call => {
-                unimplemented!("Calling conventions not yet specified")
+                let target = self.read_operand()?;
+                let ret_addr = self.pc;
+                self.jump_to(target)?;
+                self.memory.push_stack(ret_addr)?;
+                Ok(false)
             }
             Opcode::Ret => {
-                unimplemented!("Calling conventions not yet specified")
+                let ret_addr = self.memory.pop_stack()?;
+                self.jump_to(ret_addr as i64)?;
+                Ok(false)
             }
             Opcode::Dalc => {
-                unimplemented!()
+                Err(VMError::Unsupported(Opcode::Dalc))
+            }
+            Opcode::Inc => {
+                let value = self.read_value()?;
+                let result = value.checked_add(1).ok_or(VMError::Overflow)?;
+                self.write_register(result)?;
+                Ok(false)
+            }
+            Opcode::Dec => {
+                let value = self.read_value()?;
+                let result = value.checked_sub(1).ok_or(VMError::Overflow)?;
+                self.write_register(result)?;
+                Ok(false)
             }
             Opcode::Add => {
-                unimplemented!()
+                let (lhs, rhs) = (self.read_value()?, self.read_value()?);
+                let result = lhs.checked_add(rhs).ok_or(VMError::Overflow)?;
+                self.write_register(result)?;
+                Ok(false)
             }
             Opcode::Sub => {
-                unimplemented!()
+                let (lhs, rhs) = (self.read_value()?, self.read_value()?);
+                let result = lhs.checked_sub(rhs).ok_or(VMError::Overflow)?;
+                self.write_register(result)?;
+                Ok(false)
             }
             Opcode::Mul => {
-                unimplemented!()
+                let (lhs, rhs) = (self.read_value()?, self.read_value()?);
+                let result = lhs.checked_mul(rhs).ok_or(VMError::Overflow)?;
+                self.write_register(result)?;
+                Ok(false)
             }
             Opcode::Div => {
-                unimplemented!()
+                let (lhs, rhs) = (self.read_value()?, self.read_value()?);
+                if rhs == 0 {
+                    return Err(VMError::DivByZero)
+                }
+                let result = lhs.checked_div(rhs).ok_or(VMError::Overflow)?;
+                self.write_register(result)?;
+                self.remainder = lhs.checked_rem(rhs).ok_or(VMError::Overflow)?;
+                Ok(false)
+            }
+            Opcode::And => {
+                let (lhs, rhs) = (self.read_value()?, self.read_value()?);
+                self.write_register(lhs & rhs)?;
+                Ok(false)
+            }
+            Opcode::Not => {
+                let value = self.read_value()?;
+                self.write_register(!value)?;
+                Ok(false)
+            }
+            Opcode::Or => {
+                let (lhs, rhs) = (self.read_value()?, self.read_value()?);
+                self.write_register(lhs | rhs)?;
+                Ok(false)
+            }
+            Opcode::Xor => {
+                let (lhs, rhs) = (self.read_value()?, self.read_value()?);
+                self.write_register(lhs ^ rhs)?;
+                Ok(false)
+            }
+            Opcode::Bsl => {
+                let (lhs, rhs) = (self.read_value()?, self.read_value()?);
+                if !(0..32).contains(&rhs) {
+                    return Err(VMError::Overflow)
+                }
+                self.write_register(lhs << rhs)?;
+                Ok(false)
+            }
+            Opcode::Bsr => {
+                let (lhs, rhs) = (self.read_value()?, self.read_value()?);
+                if !(0..32).contains(&rhs) {
+                    return Err(VMError::Overflow)
+                }
+                self.write_register(lhs >> rhs)?;
+                Ok(false)
             }
             Opcode::Igl => {
-                return Err(VMError::IglOpcode)
+                Err(VMError::IglOpcode)
             }
-            oc @ _ => {
-                unimplemented!("{:?} opcode not yet supported", oc)
+            oc @ (Opcode::Prt | Opcode::Open | Opcode::Clse | Opcode::Read | Opcode::Wrt) => {
+                Err(VMError::Unsupported(oc))
             }
         }
     }
@@ -153,48 +333,202 @@ impl VM {
     }
 
     /// Gets the next byte in the program as an operand
-    fn next_8_bits(&mut self) -> u8 {
-        let byte = self.program[self.pc];
+    fn next_8_bits(&mut self) -> Result<u8, VMError> {
+        let byte = *self.program.get(self.pc).ok_or(VMError::SegFault)?;
         self.pc += 1;
 
-        byte
+        Ok(byte)
     }
 
-    fn read_i32(&mut self) -> i32 {
-        let buf: [u8; 4] = self.program[self.pc..self.pc + 4].try_into().unwrap();
+    fn read_i32(&mut self) -> Result<i32, VMError> {
+        let buf: [u8; 4] = self.program.get(self.pc..self.pc + 4)
+            .ok_or(VMError::SegFault)?
+            .try_into().unwrap();
         self.pc += 4;
-        LittleEndian::read_i32(&buf)
+        Ok(LittleEndian::read_i32(&buf))
     }
 
-    fn read_i64(&mut self) -> i64 {
-        let buf: [u8; 8] = self.program[self.pc..self.pc + 8].try_into().unwrap();
+    fn read_i64(&mut self) -> Result<i64, VMError> {
+        let buf: [u8; 8] = self.program.get(self.pc..self.pc + 8)
+            .ok_or(VMError::SegFault)?
+            .try_into().unwrap();
         self.pc += 8;
-        LittleEndian::read_i64(&buf)
+        Ok(LittleEndian::read_i64(&buf))
+    }
+
+    /// Reads a register number.
+    fn read_register(&mut self) -> Result<usize, VMError> {
+        let reg = self.next_8_bits()? as usize;
+        if reg > 31 {
+            return Err(VMError::OpcodeErr)
+        }
+        Ok(reg)
+    }
+
+    /// Reads a flagged operand (literal, pointer or register)
+    /// and returns its value.
+    ///
+    /// Pointers are not dereferenced, so the value is the address itself.
+    fn read_operand(&mut self) -> Result<i64, VMError> {
+        match self.next_8_bits()? {
+            LIT_FLAG => Ok(self.read_i32()? as i64),
+            REG_FLAG => Ok(self.registers[self.read_register()?] as i64),
+            flag => self.read_address(flag),
+        }
+    }
+
+    /// Reads a flagged operand that must be a literal or a register.
+    fn read_value(&mut self) -> Result<i32, VMError> {
+        match self.next_8_bits()? {
+            LIT_FLAG => self.read_i32(),
+            REG_FLAG => Ok(self.registers[self.read_register()?]),
+            _ => Err(VMError::OpcodeErr)
+        }
+    }
+
+    /// Reads the rest of a pointer operand whose flag has already been read,
+    /// and computes the address it points to.
+    fn read_address(&mut self, flag: u8) -> Result<i64, VMError> {
+        match flag {
+            PTR_FLAG => self.read_i64(),
+            IND_FLAG => {
+                let base = self.read_index_register()?;
+                let index = self.read_index_register()?;
+                let scale = self.next_8_bits()? as i64;
+                let disp = self.read_i32()? as i64;
+                Ok(base + index * scale + disp)
+            }
+            _ => Err(VMError::OpcodeErr)
+        }
+    }
+
+    /// Reads the base or index register of an indexed pointer,
+    /// returning zero if it is unused.
+    fn read_index_register(&mut self) -> Result<i64, VMError> {
+        match self.next_8_bits()? {
+            NO_REG => Ok(0),
+            reg if reg <= 31 => Ok(self.registers[reg as usize] as i64),
+            _ => Err(VMError::OpcodeErr)
+        }
+    }
+
+    /// Reads an unflagged destination register and writes a value into it.
+    fn write_register(&mut self, value: i32) -> Result<(), VMError> {
+        let reg = self.read_register()?;
+        self.set_register(reg, value);
+        Ok(())
+    }
+
+    /// Writes a register, recording its old value in the journal.
+    fn set_register(&mut self, reg: usize, value: i32) {
+        if let Some(entry) = self.journal.as_mut().and_then(Journal::current) {
+            entry.registers.push((reg, self.registers[reg]));
+        }
+        self.registers[reg] = value;
+    }
+
+    /// Reads a 4-byte value from memory.
+    ///
+    /// Addresses below `HEAP_BASE` refer to the loaded program,
+    /// and addresses from `HEAP_BASE` up refer to the heap. Reading
+    /// past the end of either is a segfault.
+    pub fn load(&self, addr: i64) -> Result<i32, VMError> {
+        let (on_heap, offset) = translate(addr)?;
+        let memory = if on_heap { self.memory.heap_bytes() } else { &self.program };
+        let bytes = memory.get(offset..offset + 4).ok_or(VMError::SegFault)?;
+        Ok(LittleEndian::read_i32(bytes))
+    }
+
+    /// Writes a 4-byte value to memory, following the same rules as `load`.
+    pub fn store(&mut self, addr: i64, value: i32) -> Result<(), VMError> {
+        let old = self.load(addr)?;
+        if let Some(entry) = self.journal.as_mut().and_then(Journal::current) {
+            entry.memory.push((addr, old));
+        }
+        self.write_word(addr, value)
+    }
+
+    fn write_word(&mut self, addr: i64, value: i32) -> Result<(), VMError> {
+        let (on_heap, offset) = translate(addr)?;
+        let memory = if on_heap { self.memory.heap_bytes_mut() } else { &mut self.program };
+        let bytes = memory.get_mut(offset..offset + 4).ok_or(VMError::SegFault)?;
+        LittleEndian::write_i32(bytes, value);
+        Ok(())
+    }
+
+    /// Sets the program counter to an absolute offset into the program.
+    ///
+    /// Targets outside of the loaded program are a segfault.
+    fn jump_to(&mut self, target: i64) -> Result<(), VMError> {
+        if target < 0 || target as usize >= self.program.len() {
+            return Err(VMError::SegFault)
+        }
+        self.pc = target as usize;
+        Ok(())
     }
 
     pub fn add_bytes(&mut self, bytes: Vec<u8>) {
         self.program.extend(bytes);
     }
 
+    /// Returns the loaded program.
+    pub fn program(&self) -> &[u8] {
+        &self.program
+    }
+
     pub fn dump_registers(&self) {
         println!("Register dump for Oxidizer VM");
-        for i in 0..32 {
-            println!("{:02}: {}", i, self.registers[i]);
+        for (i, reg) in self.registers.iter().enumerate() {
+            println!("{:02}: {}", i, reg);
         }
+        println!("eq: {}", self.eq);
+        println!("rem: {}", self.remainder);
         println!("End of register dump")
     }
 
     pub fn dump_program(&self) {
-        println!("Dumping loaded program vector");
-        println!("{:?}", self.program);
+        println!("Dumping loaded program");
+        for line in Disassembler::new(&self.program).disassemble() {
+            println!("{}", line);
+        }
         println!("End of program dump")
     }
 
+    /// Returns the contents of all 32 registers.
+    pub fn registers(&self) -> &[i32; 32] {
+        &self.registers
+    }
+
+    /// Returns the current program counter.
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    /// Moves the program counter, e.g. to the entry point of a program.
+    pub fn set_pc(&mut self, pc: usize) {
+        self.pc = pc;
+    }
+
     pub fn heap(&self) -> usize {
         self.memory.size()
     }
 
+    pub fn memory(&self) -> &VMMemory {
+        &self.memory
+    }
+
+    /// Returns the state of the flag set by the comparison opcodes.
+    pub fn eq_flag(&self) -> bool {
+        self.eq
+    }
+
+    /// Returns the remainder of the last `div` instruction.
+    pub fn remainder(&self) -> i32 {
+        self.remainder
+    }
+
     #[cfg(test)]
+    #[allow(clippy::result_unit_err)]
     pub fn test_register(&self, reg: usize) -> Result<i32, ()> {
         if reg > 31 {
             return Err(())
@@ -204,11 +538,38 @@ impl VM {
     }
 }
 
+/// Where `mov` writes its value.
+enum Dest {
+    Register(usize),
+    Memory(i64),
+}
+
+/// Splits an address into whether it is on the heap and its offset
+/// into the heap or program.
+fn translate(addr: i64) -> Result<(bool, usize), VMError> {
+    if addr < 0 {
+        return Err(VMError::SegFault)
+    }
+    let addr = addr as usize;
+    if addr >= HEAP_BASE {
+        Ok((true, addr - HEAP_BASE))
+    } else {
+        Ok((false, addr))
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum VMError {
     IglOpcode,
     SegFault,
     OpcodeErr,
+    DivByZero,
+    Overflow,
+    StackOverflow,
+    StackUnderflow,
+    TraceWrite,
+    InvalidSize(i32),
+    Unsupported(Opcode),
 }
 
 impl std::error::Error for VMError {}
@@ -225,11 +586,50 @@ impl std::fmt::Display for VMError {
             Self::OpcodeErr => {
                 write!(f, "VM Error: error while processing opcode")
             }
+            Self::DivByZero => {
+                write!(f, "VM Error: attempted to divide by zero")
+            }
+            Self::Overflow => {
+                write!(f, "VM Error: arithmetic overflow")
+            }
+            Self::StackOverflow => {
+                write!(f, "VM Error: stack overflow")
+            }
+            Self::StackUnderflow => {
+                write!(f, "VM Error: popped from an empty stack frame")
+            }
+            Self::TraceWrite => {
+                write!(f, "VM Error: could not write to the trace")
+            }
+            Self::InvalidSize(size) => {
+                write!(f, "VM Error: cannot allocate {} bytes", size)
+            }
+            Self::Unsupported(op) => {
+                write!(f, "VM Error: {} is not supported yet", format!("{:?}", op).to_lowercase())
+            }
         }
     }
 }
 
-#[derive(Debug, Clone)]
+/// The maximum size of the stack in bytes.
+pub const STACK_LIMIT: usize = 1 << 20;
+
+/// The address of the first byte of the heap.
+pub const HEAP_BASE: usize = 0x4000_0000;
+
+/// The top of the stack and the size of the heap at some point,
+/// saved in the journal.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemoryState {
+    topptr: usize,
+    baseptr: usize,
+    heap_size: usize,
+    /// Where the saved part of the stack starts.
+    start: usize,
+    top: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct VMMemory {
     heap: Vec<u8>,
     stack: Vec<u8>,
@@ -256,6 +656,14 @@ impl VMMemory {
         target
     }
 
+    pub fn heap_bytes(&self) -> &[u8] {
+        &self.heap
+    }
+
+    pub fn heap_bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.heap
+    }
+
     pub fn allocate_stack(&mut self, size: usize) -> (usize, usize) {
         let target = self.topptr + size;
         self.stack.resize(target, 0);
@@ -264,22 +672,121 @@ impl VMMemory {
         (self.baseptr, self.topptr)
     }
 
-    pub fn push_stack(&mut self, size: usize) -> (usize, usize) {
-        self.allocate_stack(size);
-        //todo: save baseptr to top of stack
-        self.baseptr += size;
-        (self.baseptr, self.topptr)
+    /// Pushes a 4-byte value onto the top of the stack.
+    pub fn push(&mut self, value: i32) -> Result<(), VMError> {
+        if self.topptr + 4 > STACK_LIMIT {
+            return Err(VMError::StackOverflow)
+        }
+        let mut buf = [0; 4];
+        LittleEndian::write_i32(&mut buf, value);
+        self.stack.extend_from_slice(&buf);
+        self.topptr += 4;
+        Ok(())
     }
 
-    pub fn pop_stack(&mut self) -> (usize, usize) {
-        unimplemented!(
-            "need to implement saving base ptr first"
-        )
+    /// Pops a 4-byte value off the top of the stack.
+    ///
+    /// Values below the base pointer belong to the caller's frame
+    /// and cannot be popped.
+    pub fn pop(&mut self) -> Result<i32, VMError> {
+        if self.topptr < self.baseptr + 4 {
+            return Err(VMError::StackUnderflow)
+        }
+        self.topptr -= 4;
+        let value = LittleEndian::read_i32(&self.stack[self.topptr..]);
+        self.stack.truncate(self.topptr);
+        Ok(value)
+    }
+
+    /// Sets up a new stack frame.
+    ///
+    /// Saves the return address and the caller's base pointer,
+    /// then moves the base pointer to the top of the stack.
+    pub fn push_stack(&mut self, ret_addr: usize) -> Result<(usize, usize), VMError> {
+        self.push(ret_addr as i32)?;
+        self.push(self.baseptr as i32)?;
+        self.baseptr = self.topptr;
+        Ok((self.baseptr, self.topptr))
+    }
+
+    /// Tears down the current stack frame, discarding anything pushed
+    /// inside it and restoring the caller's base pointer.
+    ///
+    /// Returns the saved return address.
+    pub fn pop_stack(&mut self) -> Result<usize, VMError> {
+        if self.baseptr < 8 {
+            return Err(VMError::StackUnderflow)
+        }
+        self.topptr = self.baseptr;
+        self.stack.truncate(self.topptr);
+        self.baseptr -= 8;
+        let saved_base = self.pop()? as usize;
+        let ret_addr = self.pop()? as usize;
+        self.baseptr = saved_base;
+        Ok(ret_addr)
     }
 
     pub fn size(&self) -> usize {
         self.heap_size + self.topptr
     }
+
+    /// Returns the number of bytes allocated on the heap.
+    pub fn heap_size(&self) -> usize {
+        self.heap_size
+    }
+
+    /// Returns the number of bytes in use on the stack.
+    pub fn stack_size(&self) -> usize {
+        self.topptr
+    }
+
+    /// Saves what an instruction could change on the stack and heap.
+    ///
+    /// Only the part of the stack that `opcode` can throw away is copied:
+    /// the top value for a `pop`, and the current frame along with the 8
+    /// bytes that set it up for a `ret`. Anything else only adds to the
+    /// top of the stack, so just its size is kept.
+    pub(crate) fn save_state(&self, opcode: Opcode) -> MemoryState {
+        let start = match opcode {
+            Opcode::Pop => self.topptr.saturating_sub(4),
+            Opcode::Ret => self.baseptr.saturating_sub(8).min(self.topptr),
+            _ => self.topptr,
+        };
+        MemoryState {
+            topptr: self.topptr,
+            baseptr: self.baseptr,
+            heap_size: self.heap_size,
+            start,
+            top: self.stack[start..].to_vec(),
+        }
+    }
+
+    /// Puts the stack and heap back to how they were when `state` was saved.
+    pub(crate) fn restore(&mut self, state: MemoryState) {
+        self.stack.truncate(state.start);
+        self.stack.extend(state.top);
+        self.topptr = state.topptr;
+        self.baseptr = state.baseptr;
+        self.heap.truncate(state.heap_size);
+        self.heap_size = state.heap_size;
+    }
+
+    /// Returns the return address of every stack frame, innermost first.
+    pub fn return_addresses(&self) -> Vec<usize> {
+        let mut addrs = Vec::new();
+        let mut base = self.baseptr;
+        while base >= 8 {
+            addrs.push(LittleEndian::read_i32(&self.stack[base - 8..]) as usize);
+            base = LittleEndian::read_i32(&self.stack[base - 4..]) as usize;
+        }
+        addrs
+    }
+}
+
+impl Default for VMMemory {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[cfg(test)]
@@ -295,19 +802,21 @@ mod tests {
             pc: 0,
             remainder: 0,
             eq: false,
+            journal: None,
+            tracer: None,
             }, 
             test_vm)
     }
 
     #[test]
     fn test_mov_opcode() {
-        let mut test_code: Vec<u8> = vec![0x01, 0x02, 0x00];
+        let mut test_code: Vec<u8> = vec![0x01, 0x02, 0x02, 0x00];
 
         test_code.extend(i32_to_bytes(500).to_vec());
         test_code.push(0x00);
         /*
         The above code means:
-        mov $2 500 (0x01 (mov), 0x02 (register 2), flag LIT (0), (500 in little endian))
+        mov $2 500 (0x01 (mov), flag REG (2), 0x02 (register 2), flag LIT (0), (500 in little endian))
         hlt (0x00)
         */
         let mut test_vm = VM::new(test_code);
@@ -320,7 +829,7 @@ mod tests {
     #[test]
     fn test_aloc_opcode() {
         // mov $2 10
-        let mut test_code: Vec<u8> = vec![0x01, 0x02, 0x00];
+        let mut test_code: Vec<u8> = vec![0x01, 0x02, 0x02, 0x00];
         test_code.extend(i32_to_bytes(10).to_vec());
         // aloc $2
         test_code.extend(vec![0x0c, 0x02, 0x02]);
@@ -333,6 +842,419 @@ mod tests {
         assert_eq!(test_vm.heap(), 10)
     }
 
+    #[test]
+    fn test_aloc_errors() {
+        // mov $2 -4
+        let mut test_code: Vec<u8> = vec![0x01, 0x02, 0x02, 0x00];
+        test_code.extend(i32_to_bytes(-4).to_vec());
+        // aloc $2
+        test_code.extend(vec![0x0c, 0x02, 0x02]);
+        let mut test_vm = VM::new(test_code);
+        assert_eq!(test_vm.run(), Err(VMError::InvalidSize(-4)));
+        assert_eq!(test_vm.heap(), 0);
+
+        // aloc $40
+        let mut test_vm = VM::new(vec![0x0c, 0x02, 40]);
+        assert_eq!(test_vm.run(), Err(VMError::OpcodeErr));
+    }
+
+    #[test]
+    fn test_unsupported_opcodes() {
+        // dalc $1
+        let mut test_vm = VM::new(vec![0x0d, 0x02, 0x01]);
+        assert_eq!(test_vm.run(), Err(VMError::Unsupported(Opcode::Dalc)));
+
+        // prt $1
+        let mut test_vm = VM::new(vec![0x12, 0x02, 0x01]);
+        let err = test_vm.run().unwrap_err();
+        assert_eq!(err, VMError::Unsupported(Opcode::Prt));
+        assert_eq!(err.to_string(), "VM Error: prt is not supported yet");
+    }
+
+    #[test]
+    fn test_mov_through_pointers() {
+        // aloc 8
+        let mut test_code: Vec<u8> = vec![0x0c, 0x00];
+        test_code.extend(i32_to_bytes(8).to_vec());
+        // mov $1 4
+        test_code.extend(vec![0x01, 0x02, 0x01, 0x00]);
+        test_code.extend(i32_to_bytes(4).to_vec());
+        // mov [$1 + HEAP_BASE] 99
+        test_code.extend(vec![0x01, IND_FLAG, 0x01, NO_REG, 0x01]);
+        test_code.extend(i32_to_bytes(HEAP_BASE as i32).to_vec());
+        test_code.push(0x00);
+        test_code.extend(i32_to_bytes(99).to_vec());
+        // mov $2 [HEAP_BASE + 4]
+        test_code.extend(vec![0x01, 0x02, 0x02, PTR_FLAG]);
+        test_code.extend((HEAP_BASE as i64 + 4).to_le_bytes().to_vec());
+        // hlt
+        test_code.push(0x00);
+
+        let mut test_vm = VM::new(test_code);
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.test_register(2).unwrap(), 99);
+        assert_eq!(test_vm.memory.heap_bytes(), &[0, 0, 0, 0, 99, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_pointer_out_of_bounds() {
+        let mut test_vm = VM::new(vec![0x00; 8]);
+        assert_eq!(test_vm.load(4), Ok(0));
+        assert_eq!(test_vm.load(5), Err(VMError::SegFault));
+        assert_eq!(test_vm.load(-1), Err(VMError::SegFault));
+        assert_eq!(test_vm.store(HEAP_BASE as i64, 1), Err(VMError::SegFault));
+
+        test_vm.memory.allocate_heap(4);
+        assert_eq!(test_vm.store(HEAP_BASE as i64, 1), Ok(()));
+        assert_eq!(test_vm.load(HEAP_BASE as i64), Ok(1));
+        assert_eq!(test_vm.load(HEAP_BASE as i64 + 1), Err(VMError::SegFault));
+    }
+
+    #[test]
+    fn test_jmp_opcode() {
+        // jmp 14
+        let mut test_code: Vec<u8> = vec![0x02, 0x00];
+        test_code.extend(i32_to_bytes(14).to_vec());
+        // mov $1 5 (skipped)
+        test_code.extend(vec![0x01, 0x02, 0x01, 0x00]);
+        test_code.extend(i32_to_bytes(5).to_vec());
+        // hlt
+        test_code.push(0x00);
+
+        let mut test_vm = VM::new(test_code);
+
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.test_register(1).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_jmp_register() {
+        // mov $4 12
+        let mut test_code: Vec<u8> = vec![0x01, 0x02, 0x04, 0x00];
+        test_code.extend(i32_to_bytes(12).to_vec());
+        // jmp $4
+        test_code.extend(vec![0x02, 0x02, 0x04]);
+        // hlt (skipped)
+        test_code.push(0x00);
+        // mov $1 5
+        test_code.extend(vec![0x01, 0x02, 0x01, 0x00]);
+        test_code.extend(i32_to_bytes(5).to_vec());
+        // hlt
+        test_code.push(0x00);
+
+        let mut test_vm = VM::new(test_code);
+
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.test_register(1).unwrap(), 5);
+    }
+
+    #[test]
+    fn test_jmpf_and_jmpb_opcodes() {
+        // jmpf 1
+        let mut test_code: Vec<u8> = vec![0x03, 0x00];
+        test_code.extend(i32_to_bytes(1).to_vec());
+        // hlt (skipped)
+        test_code.push(0x00);
+        // mov $1 5
+        test_code.extend(vec![0x01, 0x02, 0x01, 0x00]);
+        test_code.extend(i32_to_bytes(5).to_vec());
+        // hlt
+        test_code.push(0x00);
+
+        let mut test_vm = VM::new(test_code);
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.test_register(1).unwrap(), 5);
+
+        // hlt
+        let mut test_code: Vec<u8> = vec![0x00];
+        // jmpb 7 (back to the hlt)
+        test_code.extend(vec![0x04, 0x00]);
+        test_code.extend(i32_to_bytes(7).to_vec());
+
+        let mut test_vm = VM::new(test_code);
+        test_vm.pc = 1;
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.pc, 1);
+    }
+
+    #[test]
+    fn test_jeq_and_jne_opcodes() {
+        // jeq 12
+        let mut test_code: Vec<u8> = vec![0x0a, 0x00];
+        test_code.extend(i32_to_bytes(12).to_vec());
+        // jne 13
+        test_code.extend(vec![0x0b, 0x00]);
+        test_code.extend(i32_to_bytes(13).to_vec());
+        // hlt
+        test_code.push(0x00);
+        // hlt
+        test_code.push(0x00);
+
+        let mut test_vm = VM::new(test_code.clone());
+        test_vm.eq = true;
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.pc, 13);
+
+        let mut test_vm = VM::new(test_code);
+        test_vm.eq = false;
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.pc, 14);
+    }
+
+    #[test]
+    fn test_jump_out_of_range() {
+        // jmp 500
+        let mut test_code: Vec<u8> = vec![0x02, 0x00];
+        test_code.extend(i32_to_bytes(500).to_vec());
+
+        let mut test_vm = VM::new(test_code);
+        assert_eq!(test_vm.run(), Err(VMError::SegFault));
+
+        // jmpb 100
+        let mut test_code: Vec<u8> = vec![0x04, 0x00];
+        test_code.extend(i32_to_bytes(100).to_vec());
+
+        let mut test_vm = VM::new(test_code);
+        assert_eq!(test_vm.run(), Err(VMError::SegFault));
+
+        // truncated jmp
+        let mut test_vm = VM::new(vec![0x02, 0x00, 0x01]);
+        assert_eq!(test_vm.run(), Err(VMError::SegFault));
+    }
+
+    #[test]
+    fn test_comparison_opcodes() {
+        // mov $1 5
+        let mut prelude: Vec<u8> = vec![0x01, 0x02, 0x01, 0x00];
+        prelude.extend(i32_to_bytes(5).to_vec());
+
+        let cases = vec![
+            (0x05, 5, true), (0x05, 6, false),
+            (0x06, 6, true), (0x06, 5, false),
+            (0x07, 4, true), (0x07, 5, false),
+            (0x08, 5, true), (0x08, 4, false),
+            (0x09, 5, true), (0x09, 6, false),
+        ];
+        for (opcode, rhs, expected) in cases {
+            // <op> $1 <rhs>
+            let mut test_code = prelude.clone();
+            test_code.extend(vec![opcode, 0x02, 0x01, 0x00]);
+            test_code.extend(i32_to_bytes(rhs).to_vec());
+            // hlt
+            test_code.push(0x00);
+
+            let mut test_vm = VM::new(test_code);
+            test_vm.run().unwrap();
+            assert_eq!(test_vm.eq_flag(), expected, "opcode {:#04x} with {}", opcode, rhs);
+        }
+    }
+
+    #[test]
+    fn test_cmp_then_jeq() {
+        // cmp 3 3
+        let mut test_code: Vec<u8> = vec![0x05, 0x00];
+        test_code.extend(i32_to_bytes(3).to_vec());
+        test_code.push(0x00);
+        test_code.extend(i32_to_bytes(3).to_vec());
+        // jeq 18
+        test_code.extend(vec![0x0a, 0x00]);
+        test_code.extend(i32_to_bytes(18).to_vec());
+        // hlt (skipped)
+        test_code.push(0x00);
+        // mov $1 1
+        test_code.extend(vec![0x01, 0x02, 0x01, 0x00]);
+        test_code.extend(i32_to_bytes(1).to_vec());
+        // hlt
+        test_code.push(0x00);
+
+        let mut test_vm = VM::new(test_code);
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.test_register(1).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_comparison_rejects_pointer() {
+        // cmp [0] 0
+        let mut test_code: Vec<u8> = vec![0x05, 0x01];
+        test_code.extend(vec![0; 8]);
+        test_code.push(0x00);
+        test_code.extend(i32_to_bytes(0).to_vec());
+
+        let mut test_vm = VM::new(test_code);
+        assert_eq!(test_vm.run(), Err(VMError::OpcodeErr));
+    }
+
+    #[test]
+    fn test_push_and_pop_opcodes() {
+        // push 7
+        let mut test_code: Vec<u8> = vec![0x0e, 0x00];
+        test_code.extend(i32_to_bytes(7).to_vec());
+        // pop $3
+        test_code.extend(vec![0x0f, 0x03]);
+        // hlt
+        test_code.push(0x00);
+
+        let mut test_vm = VM::new(test_code);
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.test_register(3).unwrap(), 7);
+        assert_eq!(test_vm.heap(), 0);
+
+        // pop $3
+        let mut test_vm = VM::new(vec![0x0f, 0x03, 0x00]);
+        assert_eq!(test_vm.run(), Err(VMError::StackUnderflow));
+
+        // ret
+        let mut test_vm = VM::new(vec![0x11, 0x00]);
+        assert_eq!(test_vm.run(), Err(VMError::StackUnderflow));
+    }
+
+    #[test]
+    fn test_recursive_call() {
+        // 0:  mov $1 5
+        let mut test_code: Vec<u8> = vec![0x01, 0x02, 0x01, 0x00];
+        test_code.extend(i32_to_bytes(5).to_vec());
+        // 8:  call @fact
+        test_code.extend(vec![0x10, 0x00]);
+        test_code.extend(i32_to_bytes(15).to_vec());
+        // 14: hlt
+        test_code.push(0x00);
+        // 15: fact: le $1 1
+        test_code.extend(vec![0x08, 0x02, 0x01, 0x00]);
+        test_code.extend(i32_to_bytes(1).to_vec());
+        // 23: jeq @base
+        test_code.extend(vec![0x0a, 0x00]);
+        test_code.extend(i32_to_bytes(51).to_vec());
+        // 29: push $1
+        test_code.extend(vec![0x0e, 0x02, 0x01]);
+        // 32: dec $1 $1
+        test_code.extend(vec![0x21, 0x02, 0x01, 0x01]);
+        // 36: call @fact
+        test_code.extend(vec![0x10, 0x00]);
+        test_code.extend(i32_to_bytes(15).to_vec());
+        // 42: pop $1
+        test_code.extend(vec![0x0f, 0x01]);
+        // 44: mul $0 $1 $0
+        test_code.extend(vec![0x24, 0x02, 0x00, 0x02, 0x01, 0x00]);
+        // 50: ret
+        test_code.push(0x11);
+        // 51: base: mov $0 1
+        test_code.extend(vec![0x01, 0x02, 0x00, 0x00]);
+        test_code.extend(i32_to_bytes(1).to_vec());
+        // 59: ret
+        test_code.push(0x11);
+
+        let mut test_vm = VM::new(test_code);
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.test_register(0).unwrap(), 120);
+        assert_eq!(test_vm.heap(), 0);
+    }
+
+    #[test]
+    fn test_stack_overflow() {
+        // 0: call 0
+        let mut test_code: Vec<u8> = vec![0x10, 0x00];
+        test_code.extend(i32_to_bytes(0).to_vec());
+
+        let mut test_vm = VM::new(test_code);
+        assert_eq!(test_vm.run(), Err(VMError::StackOverflow));
+    }
+
+    #[test]
+    fn test_journal_saves_discarded_stack() {
+        let source = "call @f\nhlt\nf: { push 1\npush 2\npush 3\nmov $1 4\npop $2\nret }";
+        let mut test_vm = VM::new(crate::assembler::Assembler::new().assemble_source(source).unwrap());
+        test_vm.enable_journal(100);
+        test_vm.run().unwrap();
+
+        // only the pop and the ret copy any of the stack
+        let saved: Vec<usize> = test_vm.journal().unwrap()
+            .entries()
+            .map(|entry| entry.stack.top.len())
+            .collect();
+        assert_eq!(saved, vec![0, 0, 0, 0, 0, 4, 16, 0]);
+    }
+
+    /// Assembles `<opcode> <lhs> <rhs> <dest>` with literal operands.
+    fn arith_code(opcode: u8, lhs: i32, rhs: i32, dest: u8) -> Vec<u8> {
+        let mut test_code: Vec<u8> = vec![opcode, 0x00];
+        test_code.extend(i32_to_bytes(lhs).to_vec());
+        test_code.push(0x00);
+        test_code.extend(i32_to_bytes(rhs).to_vec());
+        test_code.push(dest);
+        test_code.push(0x00);
+
+        test_code
+    }
+
+    #[test]
+    fn test_arithmetic_opcodes() {
+        let cases = vec![
+            (0x22, 7, 3, 10),
+            (0x23, 7, 3, 4),
+            (0x24, 7, 3, 21),
+            (0x25, 7, 3, 2),
+            (0x26, 0b1100, 0b1010, 0b1000),
+            (0x28, 0b1100, 0b1010, 0b1110),
+            (0x29, 0b1100, 0b1010, 0b0110),
+            (0x2a, 1, 4, 16),
+            (0x2b, -16, 2, -4),
+        ];
+        for (opcode, lhs, rhs, expected) in cases {
+            let mut test_vm = VM::new(arith_code(opcode, lhs, rhs, 4));
+            test_vm.run().unwrap();
+            assert_eq!(test_vm.test_register(4).unwrap(), expected, "opcode {:#04x}", opcode);
+        }
+    }
+
+    #[test]
+    fn test_unary_opcodes() {
+        // mov $1 41
+        let mut test_code: Vec<u8> = vec![0x01, 0x02, 0x01, 0x00];
+        test_code.extend(i32_to_bytes(41).to_vec());
+        // inc $1 $2
+        test_code.extend(vec![0x20, 0x02, 0x01, 0x02]);
+        // dec $1 $3
+        test_code.extend(vec![0x21, 0x02, 0x01, 0x03]);
+        // not 0 $4
+        test_code.extend(vec![0x27, 0x00]);
+        test_code.extend(i32_to_bytes(0).to_vec());
+        test_code.push(0x04);
+        // hlt
+        test_code.push(0x00);
+
+        let mut test_vm = VM::new(test_code);
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.test_register(2).unwrap(), 42);
+        assert_eq!(test_vm.test_register(3).unwrap(), 40);
+        assert_eq!(test_vm.test_register(4).unwrap(), -1);
+    }
+
+    #[test]
+    fn test_div_remainder() {
+        let mut test_vm = VM::new(arith_code(0x25, 17, 5, 1));
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.test_register(1).unwrap(), 3);
+        assert_eq!(test_vm.remainder(), 2);
+    }
+
+    #[test]
+    fn test_arithmetic_errors() {
+        let mut test_vm = VM::new(arith_code(0x25, 17, 0, 1));
+        assert_eq!(test_vm.run(), Err(VMError::DivByZero));
+
+        let mut test_vm = VM::new(arith_code(0x22, i32::MAX, 1, 1));
+        assert_eq!(test_vm.run(), Err(VMError::Overflow));
+
+        let mut test_vm = VM::new(arith_code(0x25, i32::MIN, -1, 1));
+        assert_eq!(test_vm.run(), Err(VMError::Overflow));
+
+        let mut test_vm = VM::new(arith_code(0x2a, 1, 32, 1));
+        assert_eq!(test_vm.run(), Err(VMError::Overflow));
+
+        let mut test_vm = VM::new(arith_code(0x22, 1, 1, 32));
+        assert_eq!(test_vm.run(), Err(VMError::OpcodeErr));
+    }
+
     fn i32_to_bytes(num: i32) -> [u8; 4] {
         let mut buf: [u8; 4] = [0, 0, 0, 0];
         buf.as_mut().write_i32::<LittleEndian>(num).unwrap();